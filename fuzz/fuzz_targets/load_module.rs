@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xm_player::Module;
+use xm_player::Player;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Module::from_bytes(data);
+
+    // Lenient loading accepts truncated data, play a bit of whatever was loaded
+    if let Ok(module) = Module::from_bytes_lenient(data) {
+        let mut player = Player::new(&module, 48000);
+        let mut buffer = vec![0i16; 48000];
+        player.render(&mut buffer);
+
+        // Whatever loads must also save as XM, which loads back
+        let mut saved = Vec::new();
+        if module.save_xm(&mut saved).is_ok() {
+            Module::from_bytes(&saved).unwrap();
+        }
+    }
+});
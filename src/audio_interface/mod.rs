@@ -1,13 +1,18 @@
+#[cfg(windows)]
 mod win32;
+#[cfg(windows)]
 use win32::Win32;
 
+/// Output device consuming interleaved 16-bit stereo samples.
 pub trait AudioInterface {
     fn get_available_samples(&self) -> usize;
     fn wait(&self) -> bool;
     fn render(&self, buffer: &[i16]);
 }
 
+/// Opens the default output device for this platform, if there is one.
 pub fn create_audio_interface() -> Option<Box<dyn AudioInterface>> {
+    #[cfg(windows)]
     if let Some(i) = Win32::create() {
         return Some(Box::new(i));
     }
@@ -0,0 +1,21 @@
+//! XM module player library.
+//!
+//! Loads FastTracker 2 modules into a [`Module`] and renders them with a [`Player`]
+//! into interleaved 16-bit stereo buffers. Audio output is optional and lives behind
+//! the [`audio_interface::AudioInterface`] trait.
+
+pub mod audio_interface;
+mod xm_player;
+
+pub use crate::xm_player::Channel;
+pub use crate::xm_player::DuplicateCheckType;
+pub use crate::xm_player::Envelope;
+pub use crate::xm_player::Instrument;
+pub use crate::xm_player::LoadError;
+pub use crate::xm_player::LoopType;
+pub use crate::xm_player::Module;
+pub use crate::xm_player::NewNoteAction;
+pub use crate::xm_player::Pattern;
+pub use crate::xm_player::Player;
+pub use crate::xm_player::Row;
+pub use crate::xm_player::Sample;
@@ -1,14 +1,13 @@
-#![feature(unchecked_math)]
-
-mod audio_interface;
-mod xm_player;
-
 use std::error;
 
+use xm_player::audio_interface;
+use xm_player::Module;
+use xm_player::Player;
+
 fn main() -> Result<(), Box<dyn error::Error>> {
-    let module = xm_player::Module::load("../../unreal.xm")?;
+    let module = Module::load("../../unreal.xm")?;
 
-    let mut player = xm_player::Player::new(&module, 48000);
+    let mut player = Player::new(&module, 48000);
 
     println!("Benchmarking...");
     //println!("Elapsed time: {}ms", player.benchmark().as_millis());
@@ -17,8 +16,12 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 
     player.print_rows = true;
 
-    let audio_iface = audio_interface::create_audio_interface().unwrap();
-    let mut buffer = [0 as i16; 48000 * 2];
+    let audio_iface = match audio_interface::create_audio_interface() {
+        Some(audio_iface) => audio_iface,
+        None => return Err("No audio output available".into()),
+    };
+
+    let mut buffer = [0i16; 48000 * 2];
 
     while audio_iface.wait() {
         let samples_to_render = audio_iface.get_available_samples();
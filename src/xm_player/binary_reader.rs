@@ -1,4 +1,8 @@
-use std::mem::transmute;
+/// Attempt to read past the end of data, `offset` is where the read started
+#[derive(Clone, Copy, Debug)]
+pub struct EndOfData {
+    pub offset: usize,
+}
 
 pub struct BinaryReader<'a> {
     pub data: &'a [u8],
@@ -6,74 +10,67 @@ pub struct BinaryReader<'a> {
 }
 
 impl<'a> BinaryReader<'a> {
-    pub fn new(data: &'a [u8]) -> BinaryReader {
+    pub fn new(data: &'a [u8]) -> BinaryReader<'a> {
         BinaryReader { data, pos: 0 }
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        if self.pos >= self.data.len() {
-            return 0;
-        }
+    // Returns next `length` bytes and advances position, or leaves position untouched
+    // when there is not enough data left
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], EndOfData> {
+        let data = self.data;
 
-        let value = self.data[self.pos];
-        self.pos += 1;
-        value
+        match self.pos.checked_add(length) {
+            Some(end) if end <= data.len() => {
+                let slice = &data[self.pos..end];
+                self.pos = end;
+                Ok(slice)
+            }
+            _ => Err(EndOfData { offset: self.pos }),
+        }
     }
 
-    pub fn read_i8(&mut self) -> i8 {
-        unsafe { transmute::<u8, i8>(self.read_u8()) }
+    pub fn read_u8(&mut self) -> Result<u8, EndOfData> {
+        Ok(self.read_bytes(1)?[0])
     }
 
-    pub fn read_u16(&mut self) -> u16 {
-        if self.pos + 1 >= self.data.len() {
-            return 0;
-        }
-
-        let value = u16::from(self.data[self.pos + 1]) << 8 | u16::from(self.data[self.pos]);
-        self.pos += 2;
-        value
+    pub fn read_i8(&mut self) -> Result<i8, EndOfData> {
+        Ok(self.read_u8()? as i8)
     }
 
-    pub fn read_i16(&mut self) -> i16 {
-        unsafe { transmute::<u16, i16>(self.read_u16()) }
+    pub fn read_u16(&mut self) -> Result<u16, EndOfData> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        if self.pos + 3 >= self.data.len() {
-            return 0;
-        }
+    pub fn read_u16_be(&mut self) -> Result<u16, EndOfData> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
 
-        let value = ((self.data[self.pos + 3] as u32) << 24)
-            | ((self.data[self.pos + 2] as u32) << 16)
-            | ((self.data[self.pos + 1] as u32) << 8)
-            | (self.data[self.pos] as u32);
+    pub fn read_i16(&mut self) -> Result<i16, EndOfData> {
+        Ok(self.read_u16()? as i16)
+    }
 
-        self.pos += 4;
-        value
+    pub fn read_u32(&mut self) -> Result<u32, EndOfData> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
 
-    pub fn read_string_segment(&mut self, segment_length: usize) -> String {
-        if self.pos + segment_length - 1 >= self.data.len() {
-            String::default()
-        } else {
-            let slice = &self.data[self.pos..self.pos + segment_length];
+    pub fn read_string_segment(&mut self, segment_length: usize) -> Result<String, EndOfData> {
+        let slice = self.read_bytes(segment_length)?;
 
-            // We have to iterate every byte and strictly convert to ASCII, because
-            // some modules have f*cked up sample & instrument names, that do not
-            // map nicely to UTF-8 strings
-            let value =
-                String::from_iter(slice.iter().map(
-                    |&ch| {
-                        if ch < 128 {
-                            ch as char
-                        } else {
-                            32 as char
-                        }
-                    },
-                ));
+        // We have to iterate every byte and strictly convert to ASCII, because
+        // some modules have f*cked up sample & instrument names, that do not
+        // map nicely to UTF-8 strings. Control characters (e.g. zero padding) become
+        // spaces, so names can be simply trimmed.
+        let value = String::from_iter(slice.iter().map(|&ch| {
+            if (32..128).contains(&ch) {
+                ch as char
+            } else {
+                32 as char
+            }
+        }));
 
-            self.pos += segment_length;
-            value
-        }
+        Ok(value)
     }
 }
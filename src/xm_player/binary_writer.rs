@@ -0,0 +1,45 @@
+#[derive(Default)]
+pub struct BinaryWriter {
+    pub data: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> BinaryWriter {
+        BinaryWriter::default()
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_i8(&mut self, value: i8) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Writes string into fixed size segment, cut or padded with zeros as needed.
+    // Only ASCII is written, anything else becomes a space.
+    pub fn write_string_segment(&mut self, value: &str, segment_length: usize) {
+        let start = self.data.len();
+
+        self.data.extend(
+            value
+                .chars()
+                .map(|ch| if ch.is_ascii() { ch as u8 } else { b' ' })
+                .take(segment_length),
+        );
+
+        self.data.resize(start + segment_length, 0);
+    }
+}
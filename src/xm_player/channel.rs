@@ -1,4 +1,3 @@
-use std::borrow::Borrow;
 use std::ops::BitAnd;
 use std::rc::Rc;
 
@@ -10,31 +9,98 @@ use super::NibbleTest;
 use super::Row;
 use super::Sample;
 
-fn get_note_period(note: f32) -> f32 {
-    1920.0 - note * 16.0
+// Fadeout volume of a note that is not fading out (yet)
+const FADEOUT_VOLUME_MAX: usize = 65536;
+
+// ProTracker periods of C-4 octave (C-2 in ProTracker numbering) with zero finetune, in
+// FT2 units which are 4 times finer. Last one is C-5 for interpolating the B-4 finetunes.
+const AMIGA_PERIODS: [f32; 13] = [
+    1712.0, 1616.0, 1525.0, 1440.0, 1357.0, 1281.0, 1209.0, 1141.0, 1077.0, 1017.0, 961.0, 907.0,
+    856.0,
+];
+
+// Note is 0-based (C-0 is 0) with finetune as fraction, period is in FT2 units
+fn get_note_period(note: f32, linear_freq_table: bool) -> f32 {
+    if linear_freq_table {
+        return 7680.0 - note * 64.0;
+    }
+
+    let note = note.clamp(0.0, 119.0);
+    let semitone = note.floor();
+    let octave = (semitone / 12.0).floor();
+    let index = (semitone - octave * 12.0) as usize;
+
+    // Finetunes are interpolated between neighbouring semitones of the table
+    let period = AMIGA_PERIODS[index]
+        + (AMIGA_PERIODS[index + 1] - AMIGA_PERIODS[index]) * (note - semitone);
+
+    period * 2.0f32.powf(4.0 - octave)
 }
 
-fn get_note_frequency(period: f32) -> f32 {
-    8363.0 * 2.0f32.powf((1152.0 - period) / 192.0)
+// Pitch slides keep period in this range (FT2)
+const MIN_PERIOD: f32 = 1.0;
+const MAX_PERIOD: f32 = 31999.0;
+
+// Half of sine wave used by vibrato & tremolo (FT2)
+const VIBRATO_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+// Vibrato & tremolo waveform magnitude 0-255 at position, second half of the wave is
+// negative. FT2 takes ramp direction from vibrato position even for tremolo, so that is
+// passed in as `ramp_pos`.
+fn get_waveform_value(waveform: u8, pos: u8, ramp_pos: u8) -> u8 {
+    let index = (pos >> 2) & 0x1F;
+
+    match waveform & 3 {
+        0 => VIBRATO_TABLE[index as usize],
+        1 => {
+            if (ramp_pos as i8) < 0 {
+                !(index << 3)
+            } else {
+                index << 3
+            }
+        }
+        _ => 255,
+    }
 }
 
-fn follow_envelope(mut ticks: usize, note_released: bool, envelope: &Envelope) -> usize {
-    if envelope.tick_values.is_empty() {
-        return ticks;
+// Inverse of `get_note_period`
+fn get_period_note(period: f32, linear_freq_table: bool) -> f32 {
+    if linear_freq_table {
+        (7680.0 - period) / 64.0
+    } else {
+        48.0 + 12.0 * (1712.0 / period.max(1.0)).log2()
     }
+}
 
-    if !note_released {
-        if ticks < envelope.sustain as usize {
-            ticks += 1
-        }
+fn get_period_frequency(period: f32, linear_freq_table: bool) -> f32 {
+    if linear_freq_table {
+        8363.0 * 2.0f32.powf((4608.0 - period) / 768.0)
     } else {
-        ticks += 1;
-        if ticks == envelope.loop_end {
-            ticks = envelope.loop_start;
-        }
+        8363.0 * 1712.0 / period.max(1.0)
     }
+}
 
-    ticks
+// Instrument auto-vibrato waveform at position 0-255, values are -64..64 (FT2)
+fn get_auto_vibrato_value(vibrato_type: u8, pos: u8) -> i32 {
+    match vibrato_type {
+        // Square
+        1 => {
+            if pos > 127 {
+                64
+            } else {
+                -64
+            }
+        }
+        // Ramp up
+        2 => ((((pos >> 1) as i32) + 64) & 127) - 64,
+        // Ramp down
+        3 => (((-((pos >> 1) as i32)) + 64) & 127) - 64,
+        // Sine, starts downwards
+        _ => -(64.0 * (pos as f32 * std::f32::consts::TAU / 256.0).sin()).round() as i32,
+    }
 }
 
 pub struct Channel<'a> {
@@ -46,18 +112,44 @@ pub struct Channel<'a> {
     note: f32,
     note_volume: usize,
     note_panning: usize,
+    default_panning: usize,
     note_period: f32,
     note_target_period: f32,
+    tone_portamento_speed: f32,
+    glissando: bool,
+    portamento_up_param: u8,
+    portamento_down_param: u8,
+    fine_portamento_up_param: u8,
+    fine_portamento_down_param: u8,
+    extra_fine_portamento_up_param: u8,
+    extra_fine_portamento_down_param: u8,
+    volume_slide_param: u8,
+    fine_volume_slide_up_param: u8,
+    fine_volume_slide_down_param: u8,
+    // Waveforms set by E4x (lower 4 bits) and E7x (upper 4 bits)
+    waveform_control: u8,
+    vibrato_pos: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_period_offset: f32,
+    tremolo_pos: u8,
+    tremolo_speed: u8,
+    tremolo_depth: u8,
+    tremolo_volume_offset: i32,
     note_frequency: f32,
     note_step: f32,
     note_released: bool,
     loop_dir_forward: bool,
     volume_envelope_ticks: usize,
     panning_envelope_ticks: usize,
-    last_nonzero_effect_param: u8,
+    fadeout_volume: usize,
+    auto_vibrato_pos: u8,
+    auto_vibrato_depth: usize,
+    auto_vibrato_sweep: usize,
     sample_offset: f32,
     final_volume: usize,
     final_panning: usize,
+    final_period: f32,
 }
 
 macro_rules! render_samples {
@@ -101,7 +193,9 @@ macro_rules! render_samples {
 }
 
 impl<'a> Channel<'a> {
-    pub fn new(module: &'a Module, sample_rate: usize) -> Self {
+    pub fn new(module: &'a Module, channel_index: usize, sample_rate: usize) -> Self {
+        let default_panning = module.get_channel_panning(channel_index) as usize;
+
         Channel {
             module,
             inv_sample_rate: 1.0 / (sample_rate as f32),
@@ -110,19 +204,44 @@ impl<'a> Channel<'a> {
             instrument: None,
             note: 0.0,
             note_volume: 0,
-            note_panning: 0,
+            note_panning: default_panning,
+            default_panning,
             note_period: 0.0,
             note_target_period: 0.0,
+            tone_portamento_speed: 0.0,
+            glissando: false,
+            portamento_up_param: 0,
+            portamento_down_param: 0,
+            fine_portamento_up_param: 0,
+            fine_portamento_down_param: 0,
+            extra_fine_portamento_up_param: 0,
+            extra_fine_portamento_down_param: 0,
+            volume_slide_param: 0,
+            fine_volume_slide_up_param: 0,
+            fine_volume_slide_down_param: 0,
+            waveform_control: 0,
+            vibrato_pos: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_period_offset: 0.0,
+            tremolo_pos: 0,
+            tremolo_speed: 0,
+            tremolo_depth: 0,
+            tremolo_volume_offset: 0,
             note_frequency: 0.0,
             note_step: 0.0,
             note_released: false,
             loop_dir_forward: true,
             volume_envelope_ticks: 0,
             panning_envelope_ticks: 0,
-            last_nonzero_effect_param: 0,
+            fadeout_volume: FADEOUT_VOLUME_MAX,
+            auto_vibrato_pos: 0,
+            auto_vibrato_depth: 0,
+            auto_vibrato_sweep: 0,
             sample_offset: 0.0,
             final_volume: 0,
             final_panning: 0,
+            final_period: 0.0,
         }
     }
 
@@ -130,63 +249,249 @@ impl<'a> Channel<'a> {
         self.instrument.is_some() && self.sample.is_some()
     }
 
-    fn tn(
-        &mut self,
-        keep_period: bool,
-        keep_volume: bool,
-        keep_position: bool,
-        keep_envelope: bool,
-    ) {
-        if !keep_period {
-            self.note_period = get_note_period(self.note as f32);
-            self.note_frequency = get_note_frequency(self.note_period);
-        }
+    fn note_on(&mut self) {
+        let sample = self.sample.clone().unwrap();
 
-        if !keep_position {
-            self.sample_offset = 0.0;
-            self.loop_dir_forward = true;
-        }
+        // Row notes are 1-based
+        self.note = (self.row.note as f32 - 1.0)
+            + sample.relative_note as f32
+            + (sample.finetune as f32) / 128.0;
+        self.note_period = get_note_period(self.note, self.module.linear_freq_table);
+        self.note_target_period = 0.0;
+        self.reset_volume_and_panning(&sample);
 
-        if let Some(sample) = self.sample.clone() {
-            if !keep_volume {
-                self.note_volume = sample.volume as usize;
+        // Vibrato & tremolo restart with every note, unless waveform control says otherwise
+        self.vibrato_period_offset = 0.0;
+        self.tremolo_volume_offset = 0;
+        if (self.waveform_control & 0x04) == 0 {
+            self.vibrato_pos = 0;
+        }
+        if (self.waveform_control & 0x40) == 0 {
+            self.tremolo_pos = 0;
+        }
+        self.note_released = false;
+        self.loop_dir_forward = true;
+        self.volume_envelope_ticks = Envelope::START_TICK;
+        self.panning_envelope_ticks = Envelope::START_TICK;
+        self.fadeout_volume = FADEOUT_VOLUME_MAX;
+
+        // Auto-vibrato depth ramps in during sweep ticks
+        if let Some(instrument) = &self.instrument {
+            let depth = (instrument.vibrato_depth as usize) << 8;
+
+            self.auto_vibrato_pos = 0;
+            if let Some(sweep) = depth.checked_div(instrument.vibrato_sweep as usize) {
+                self.auto_vibrato_depth = 0;
+                self.auto_vibrato_sweep = sweep;
+            } else {
+                self.auto_vibrato_depth = depth;
+                self.auto_vibrato_sweep = 0;
             }
+        }
+
+        self.sample_offset = 0.0;
+    }
 
+    fn reset_volume_and_panning(&mut self, sample: &Sample) {
+        self.note_volume = sample.volume as usize;
+        if sample.has_panning {
             self.note_panning = sample.panning as usize;
         }
     }
 
-    fn note_on(&mut self) {
-        let instrument = self.instrument.as_ref().unwrap();
-        let sample = self.sample.as_ref().unwrap();
+    // New note on tone portamento row only sets the period to slide to. Instrument number
+    // still resets volume & panning, but the sample keeps playing.
+    fn set_portamento_target(&mut self) {
+        let sample = self.sample.clone().unwrap();
 
-        self.note =
-            self.row.note as f32 + sample.relative_note as f32 + (sample.finetune as f32) / 128.0;
-        self.note_period = get_note_period(self.note as f32);
-        self.note_target_period = 0.0;
-        self.note_frequency = get_note_frequency(self.note_period);
+        let note = (self.row.note as f32 - 1.0)
+            + sample.relative_note as f32
+            + (sample.finetune as f32) / 128.0;
+        self.note_target_period = get_note_period(note, self.module.linear_freq_table);
+
+        if self.row.instrument > 0 {
+            self.reset_volume_and_panning(&sample);
+        }
+    }
+
+    fn tone_portamento(&mut self) {
+        if self.note_target_period <= 0.0 {
+            return;
+        }
+
+        if self.note_period < self.note_target_period {
+            self.note_period =
+                (self.note_period + self.tone_portamento_speed).min(self.note_target_period);
+        } else {
+            self.note_period =
+                (self.note_period - self.tone_portamento_speed).max(self.note_target_period);
+        }
+    }
+
+    // Slides period by `delta` (negative is up), zero parameter continues with the last one
+    // stored in `memory`
+    fn portamento(&mut self, param: u8, memory: fn(&mut Self) -> &mut u8, delta: f32) {
+        let memory = memory(self);
+        if param != 0 {
+            *memory = param;
+        }
+
+        let amount = *memory as f32 * delta;
+        self.note_period = (self.note_period + amount).clamp(MIN_PERIOD, MAX_PERIOD);
+    }
+
+    // Zero parameter nibbles keep previous speed & depth. Depth is kept in quarters, so
+    // fine vibrato shares it with normal one.
+    fn set_vibrato_param(&mut self, param: u8, depth_multiplier: u8) {
+        if (param & 0xF0) != 0 {
+            self.vibrato_speed = (param >> 4) * 4;
+        }
+        if (param & 0x0F) != 0 {
+            self.vibrato_depth = (param & 0x0F) * depth_multiplier;
+        }
+    }
+
+    // Offsets period from the base one, which is left as is
+    fn vibrato(&mut self) {
+        let waveform = self.waveform_control & 0x0F;
+        let value = get_waveform_value(waveform, self.vibrato_pos, self.vibrato_pos) as i32;
+        let offset = ((value * self.vibrato_depth as i32) >> 7) as f32;
+
+        self.vibrato_period_offset = if (self.vibrato_pos as i8) < 0 {
+            -offset
+        } else {
+            offset
+        };
+
+        self.vibrato_pos = self.vibrato_pos.wrapping_add(self.vibrato_speed);
+    }
+
+    // Offsets volume from the base one, which is left as is
+    fn tremolo(&mut self, param: u8) {
+        if (param & 0xF0) != 0 {
+            self.tremolo_speed = (param >> 4) * 4;
+        }
+        if (param & 0x0F) != 0 {
+            self.tremolo_depth = param & 0x0F;
+        }
+
+        let waveform = self.waveform_control >> 4;
+        let value = get_waveform_value(waveform, self.tremolo_pos, self.vibrato_pos) as i32;
+        let offset = (value * self.tremolo_depth as i32) >> 6;
+
+        self.tremolo_volume_offset = if (self.tremolo_pos as i8) < 0 {
+            -offset
+        } else {
+            offset
+        };
+
+        self.tremolo_pos = self.tremolo_pos.wrapping_add(self.tremolo_speed);
+    }
+
+    // Zero parameter continues with the last one, on all ticks but the first
+    fn volume_slide(&mut self, param: u8, row_tick_index: usize) {
+        if param != 0 {
+            self.volume_slide_param = param;
+        }
+
+        if row_tick_index == 0 {
+            return;
+        }
+
+        // Slide up wins when both are given
+        let up = (self.volume_slide_param >> 4) as i32;
+        let down = (self.volume_slide_param & 0x0F) as i32;
+
+        self.slide_volume(if up > 0 { up } else { -down });
+    }
+
+    // Fine volume slide, first tick only
+    fn fine_volume_slide(&mut self, param: u8, memory: fn(&mut Self) -> &mut u8, sign: i32) {
+        let memory = memory(self);
+        if param != 0 {
+            *memory = param;
+        }
+
+        let amount = *memory as i32 * sign;
+        self.slide_volume(amount);
+    }
+
+    fn slide_volume(&mut self, amount: i32) {
+        self.note_volume = (self.note_volume as i32 + amount).clamp(0, 64) as usize;
+    }
+
+    fn slide_panning(&mut self, amount: i32) {
+        self.note_panning = (self.note_panning as i32 + amount).clamp(0, 255) as usize;
+    }
+
+    // Period played this tick. With glissando tone portamento moves in semitones,
+    // keeping the finetune of the sample. Other slides are never rounded.
+    fn get_output_period(&self) -> f32 {
+        let linear_freq_table = self.module.linear_freq_table;
+        let tone_portamento = self.row.has_portamento() && self.note_target_period > 0.0;
+
+        match &self.sample {
+            Some(sample) if self.glissando && tone_portamento => {
+                let finetune = (sample.finetune as f32) / 128.0;
+                let note = get_period_note(self.note_period, linear_freq_table) - finetune;
+
+                get_note_period(note.round() + finetune, linear_freq_table)
+            }
+            _ => self.note_period,
+        }
+    }
+
+    fn set_frequency(&mut self, period: f32) {
+        self.note_frequency = get_period_frequency(period, self.module.linear_freq_table);
         self.note_step = self.note_frequency * self.inv_sample_rate;
-        self.note_volume = sample.volume as usize;
-        self.note_panning = sample.panning as usize;
-        self.note_released = false;
-        self.loop_dir_forward = true;
-        self.volume_envelope_ticks = 0;
-        self.panning_envelope_ticks = 0;
+    }
+
+    fn tick_auto_vibrato(&mut self) {
+        let Some(instrument) = self.instrument.clone() else {
+            return;
+        };
 
-        // Set initial note volume
-        if self.row.volume >= 0x10 && self.row.volume <= 0x50 {
-            self.note_volume *= (self.row.volume - 16) as usize;
-            self.note_volume /= 64;
+        if instrument.vibrato_depth == 0 {
+            return;
         }
-        // Set initial note panning
-        else if self.row.volume >= 0xC0 && self.row.volume <= 0xCF {
-            self.note_panning = ((self.row.volume & 0x0F) * 17) as usize;
+
+        let max_depth = (instrument.vibrato_depth as usize) << 8;
+
+        // Released note stops sweeping, keeping the depth reached so far (FT2)
+        if self.auto_vibrato_sweep > 0 && !self.note_released {
+            self.auto_vibrato_depth += self.auto_vibrato_sweep;
+            if self.auto_vibrato_depth >= max_depth {
+                self.auto_vibrato_depth = max_depth;
+                self.auto_vibrato_sweep = 0;
+            }
         }
 
-        self.sample_offset = 0.0;
+        self.auto_vibrato_pos = self.auto_vibrato_pos.wrapping_add(instrument.vibrato_rate);
+
+        let value = get_auto_vibrato_value(instrument.vibrato_type, self.auto_vibrato_pos);
+        let offset = (value * self.auto_vibrato_depth as i32) >> 14;
+
+        self.final_period += offset as f32;
     }
 
     fn note_off(&mut self) {
+        if !self.note_released {
+            if let Some(instrument) = &self.instrument {
+                self.volume_envelope_ticks = instrument
+                    .volume_envelope
+                    .release(self.volume_envelope_ticks);
+                self.panning_envelope_ticks = instrument
+                    .panning_envelope
+                    .release(self.panning_envelope_ticks);
+
+                // Without volume envelope there's nothing to fade out, so key-off cuts the
+                // note right away (FT2)
+                if instrument.volume_envelope.tick_values.is_empty() {
+                    self.note_volume = 0;
+                }
+            }
+        }
+
         self.note_released = true;
     }
 
@@ -196,96 +501,306 @@ impl<'a> Channel<'a> {
         self.sample = None;
     }
 
-    fn apply_effects(&mut self, row_tick_index: usize) {
-        // Volume slide down (or fine slide down)
-        if self.row.volume.test_high_nibble(0x60)
-            || (self.row.volume.test_high_nibble(0x80) && row_tick_index == 0)
-        {
-            self.note_volume = self
-                .note_volume
-                .saturating_sub(self.row.volume.bitand(0x0F) as usize);
+    fn apply_effects(&mut self, row_tick_index: usize, speed: usize) {
+        // Volume column works on any row, with or without note. Slides have no memory,
+        // regular slides happen on all ticks but the first, fine slides on the first only.
+        let volume_param = self.row.volume.bitand(0x0F) as i32;
+
+        match self.row.volume {
+            // Set volume
+            0x10..=0x50 if row_tick_index == 0 => {
+                self.note_volume = (self.row.volume - 0x10) as usize;
+            }
+            // Volume slide down & up
+            0x60..=0x6F if row_tick_index > 0 => self.slide_volume(-volume_param),
+            0x70..=0x7F if row_tick_index > 0 => self.slide_volume(volume_param),
+            // Fine volume slide down & up
+            0x80..=0x8F if row_tick_index == 0 => self.slide_volume(-volume_param),
+            0x90..=0x9F if row_tick_index == 0 => self.slide_volume(volume_param),
+            // Set panning
+            0xC0..=0xCF if row_tick_index == 0 => {
+                self.note_panning = (volume_param as usize) << 4;
+            }
+            // Panning slide left & right
+            0xD0..=0xDF if row_tick_index > 0 => self.slide_panning(-volume_param),
+            0xE0..=0xEF if row_tick_index > 0 => self.slide_panning(volume_param),
+            _ => {}
         }
-        // Volume slide up (or fine slide up)
-        else if self.row.volume.test_high_nibble(0x70)
-            || (self.row.volume.test_high_nibble(0x90) && row_tick_index == 0)
-        {
-            self.note_volume += self.row.volume.bitand(0x0F) as usize;
-            self.note_volume = self.note_volume.clamp(0, 64);
+
+        // Vibrato & tremolo offsets last only while the effect does. First tick of the row
+        // keeps the last offset, since the effect is only updated on other ticks (FT2).
+        let has_vibrato = matches!(self.row.effect_type, 0x04 | 0x06 | 0x1E)
+            || self.row.volume.test_high_nibble(0xB0);
+        if !has_vibrato {
+            self.vibrato_period_offset = 0.0;
+        }
+        if self.row.effect_type != 0x07 {
+            self.tremolo_volume_offset = 0;
         }
 
-        if self.row.effect_param != 0 {
-            self.last_nonzero_effect_param = self.row.effect_param;
+        // Set vibrato speed
+        if self.row.volume.test_high_nibble(0xA0) && (self.row.volume & 0x0F) != 0 {
+            self.vibrato_speed = (self.row.volume & 0x0F) * 4;
+        }
+        // Vibrato, depth in lower 4 bits
+        else if self.row.volume.test_high_nibble(0xB0) {
+            self.set_vibrato_param(self.row.volume & 0x0F, 4);
+
+            if row_tick_index > 0 {
+                self.vibrato();
+            }
         }
 
+        // Tone portamento, speed in lower 4 bits
+        if self.row.volume.test_high_nibble(0xF0) {
+            if self.row.volume & 0x0F != 0 {
+                self.tone_portamento_speed = ((self.row.volume & 0x0F) as f32) * 64.0;
+            }
+
+            if row_tick_index > 0 {
+                self.tone_portamento();
+            }
+        }
+
+        let param = self.row.effect_param;
+        let slide_step = if row_tick_index > 0 { 4.0 } else { 0.0 };
+        let mut arpeggio_note = 0;
+
         match self.row.effect_type {
+            // Arpeggio. FT2 picks the note from ticks left in the row (so order depends on
+            // speed) and past 16 ticks reads from a table that isn't there.
+            0x00 if param != 0 && row_tick_index > 0 => {
+                let speed = speed.max(1);
+                arpeggio_note = match speed - (row_tick_index % speed) {
+                    16 => 0,
+                    ticks_left if ticks_left > 16 || ticks_left % 3 == 2 => param & 0x0F,
+                    ticks_left if ticks_left % 3 == 1 => param >> 4,
+                    _ => 0,
+                };
+            }
+            // Portamento up & down, first tick only remembers the parameter
+            0x01 => {
+                self.portamento(param, |c| &mut c.portamento_up_param, -slide_step);
+            }
+            0x02 => {
+                self.portamento(param, |c| &mut c.portamento_down_param, slide_step);
+            }
+            // Tone portamento
+            0x03 => {
+                if param != 0 {
+                    self.tone_portamento_speed = (param as f32) * 4.0;
+                }
+
+                if row_tick_index > 0 {
+                    self.tone_portamento();
+                }
+            }
+            // Tone portamento with previous speed and volume slide
+            0x05 => {
+                if row_tick_index > 0 {
+                    self.tone_portamento();
+                }
+
+                self.volume_slide(param, row_tick_index);
+            }
+            // Vibrato
+            0x04 if row_tick_index > 0 => {
+                self.set_vibrato_param(param, 4);
+                self.vibrato();
+            }
+            // Vibrato with previous speed & depth and volume slide
+            0x06 => {
+                if row_tick_index > 0 {
+                    self.vibrato();
+                }
+
+                self.volume_slide(param, row_tick_index);
+            }
+            // Fine vibrato, only loaded from S3M & IT
+            0x1E if row_tick_index > 0 => {
+                self.set_vibrato_param(param, 1);
+                self.vibrato();
+            }
+            // Tremolo
+            0x07 if row_tick_index > 0 => {
+                self.tremolo(param);
+            }
             // Set panning
-            0x08 => {
-                self.note_panning = self.row.effect_param as usize;
+            0x08 if row_tick_index == 0 => {
+                self.note_panning = param as usize;
             }
             // Volume slide
             0x0A => {
-                self.note_volume = self
-                    .note_volume
-                    .saturating_sub(self.last_nonzero_effect_param as usize);
+                self.volume_slide(param, row_tick_index);
             }
-            // Tone portamento
-            0x03 => {
-                //
+            // Set volume
+            0x0C if row_tick_index == 0 => {
+                self.note_volume = (param as usize).min(64);
+            }
+            // Fine portamento up & down, first tick only
+            0x0E if param.test_high_nibble(0x10) && row_tick_index == 0 => {
+                self.portamento(param & 0x0F, |c| &mut c.fine_portamento_up_param, -4.0);
+            }
+            0x0E if param.test_high_nibble(0x20) && row_tick_index == 0 => {
+                self.portamento(param & 0x0F, |c| &mut c.fine_portamento_down_param, 4.0);
+            }
+            // Glissando control
+            0x0E if param.test_high_nibble(0x30) => {
+                self.glissando = (param & 0x0F) != 0;
+            }
+            // Fine volume slide up & down
+            0x0E if param.test_high_nibble(0xA0) && row_tick_index == 0 => {
+                self.fine_volume_slide(param & 0x0F, |c| &mut c.fine_volume_slide_up_param, 1);
+            }
+            0x0E if param.test_high_nibble(0xB0) && row_tick_index == 0 => {
+                self.fine_volume_slide(param & 0x0F, |c| &mut c.fine_volume_slide_down_param, -1);
+            }
+            // Vibrato & tremolo waveform: sine, ramp, square, bit 2 keeps position for new notes
+            0x0E if param.test_high_nibble(0x40) => {
+                self.waveform_control = (self.waveform_control & 0xF0) | (param & 0x0F);
+            }
+            0x0E if param.test_high_nibble(0x70) => {
+                self.waveform_control = (self.waveform_control & 0x0F) | ((param & 0x0F) << 4);
+            }
+            // Note cut on tick x, the note keeps playing at zero volume
+            0x0E if param.test_high_nibble(0xC0) && row_tick_index == (param & 0x0F) as usize => {
+                self.note_volume = 0;
+            }
+            // Extra fine portamento up & down, first tick only
+            0x21 if param.test_high_nibble(0x10) && row_tick_index == 0 => {
+                self.portamento(
+                    param & 0x0F,
+                    |c| &mut c.extra_fine_portamento_up_param,
+                    -1.0,
+                );
+            }
+            0x21 if param.test_high_nibble(0x20) && row_tick_index == 0 => {
+                self.portamento(
+                    param & 0x0F,
+                    |c| &mut c.extra_fine_portamento_down_param,
+                    1.0,
+                );
             }
             _ => {}
         }
 
-        self.final_volume = self.note_volume;
+        self.final_volume =
+            (self.note_volume as i32 + self.tremolo_volume_offset).clamp(0, 64) as usize;
         self.final_panning = self.note_panning;
+        self.final_period = self.get_output_period();
+
+        // Arpeggio plays semitones above current period
+        if arpeggio_note > 0 {
+            let linear_freq_table = self.module.linear_freq_table;
+            let note = get_period_note(self.final_period, linear_freq_table);
+
+            self.final_period = get_note_period(note + arpeggio_note as f32, linear_freq_table);
+        }
+
+        self.final_period += self.vibrato_period_offset;
     }
 
     fn tick_envelopes(&mut self) {
         if let Some(instrument) = self.instrument.clone() {
-            self.volume_envelope_ticks = follow_envelope(
-                self.volume_envelope_ticks,
-                self.note_released,
-                &instrument.volume_envelope,
-            );
-
-            let volume = instrument
+            self.volume_envelope_ticks = instrument
                 .volume_envelope
-                .get_value(self.volume_envelope_ticks) as usize;
-
-            self.final_volume = ((self.note_volume as usize) * volume) / 64;
+                .follow(self.volume_envelope_ticks, self.note_released);
+
+            // Instruments without volume envelope (e.g. from MOD files) play at full volume
+            let volume = if instrument.volume_envelope.tick_values.is_empty() {
+                64
+            } else {
+                instrument
+                    .volume_envelope
+                    .get_value(self.volume_envelope_ticks) as usize
+            };
+
+            // Released notes fade out, voice is freed once they're silent
+            if self.note_released {
+                self.fadeout_volume = self
+                    .fadeout_volume
+                    .saturating_sub(instrument.volume_envelope.fadeout as usize);
+
+                if self.fadeout_volume == 0 {
+                    self.note_kill();
+                }
+            }
 
-            self.panning_envelope_ticks = follow_envelope(
-                self.panning_envelope_ticks,
-                self.note_released,
-                &instrument.panning_envelope,
-            );
+            self.final_volume =
+                (self.final_volume * volume * self.fadeout_volume) / (64 * FADEOUT_VOLUME_MAX);
+
+            self.panning_envelope_ticks = instrument
+                .panning_envelope
+                .follow(self.panning_envelope_ticks, self.note_released);
+
+            // Panning envelope swings around note panning (envelope value 32 is center),
+            // but only as far as the distance to the nearest edge allows (FT2)
+            if !instrument.panning_envelope.tick_values.is_empty() {
+                let envelope_panning = instrument
+                    .panning_envelope
+                    .get_value(self.panning_envelope_ticks)
+                    as i32;
+                let panning = self.note_panning.clamp(0, 255) as i32;
+                let range = 128 - (panning - 128).abs();
+
+                self.final_panning =
+                    (panning + ((envelope_panning - 32) * range) / 32).clamp(0, 255) as usize;
+            }
         }
     }
 
+    /// Period played on the last tick
+    pub fn get_period(&self) -> f32 {
+        self.final_period
+    }
+
+    /// Volume 0-64 played on the last tick
+    pub fn get_volume(&self) -> usize {
+        self.final_volume
+    }
+
+    /// Panning 0-255 played on the last tick
+    pub fn get_panning(&self) -> usize {
+        self.final_panning
+    }
+
     pub fn reset(&mut self) {
         self.note_kill();
+        self.note_panning = self.default_panning;
     }
 
-    pub fn tick(&mut self, mut row: Row, row_tick_index: usize, buffer: &mut [i16]) {
+    pub fn tick(
+        &mut self,
+        row: Row,
+        row_tick_index: usize,
+        speed: usize,
+        global_volume: usize,
+        buffer: &mut [i16],
+    ) {
+        // Note delay (EDx) starts the row on tick x, only the delay effect is kept until then
+        let note_delay = if row.effect_type == 0x0E && row.effect_param.test_high_nibble(0xD0) {
+            (row.effect_param & 0x0F) as usize
+        } else {
+            0
+        };
+
+        if row_tick_index == 0 && note_delay > 0 {
+            self.row = Row {
+                effect_type: row.effect_type,
+                effect_param: row.effect_param,
+                ..Row::default()
+            };
+        }
+
         // Decode note in row
-        if row_tick_index == 0 {
-            let mut trigger_note = true;
-            let mut keep_period = false;
-            let mut keep_volume = false;
-            let mut keep_position = false;
-            let mut keep_envelope = false;
-
-            if row.instrument > 0 {
-                if self.row.has_portamento() && self.is_note_active() {
-                    keep_period = true;
-                    keep_position = true;
-                    trigger_note = true;
-                } else if row.note == 0 && self.is_note_active() {
-                    keep_position = true;
-                    trigger_note = true;
-                } else if let Some(instrument) =
-                    self.module.get_instrument(self.row.instrument as usize)
-                {
-                    if let Some(sample) = instrument.get_note_sample_ref(self.row.note as usize) {
+        if row_tick_index == note_delay {
+            // Instrument and note numbers in rows are 1-based. Portamento keeps current
+            // instrument & sample playing.
+            let portamento = row.has_portamento() && self.is_note_active();
+
+            if row.instrument > 0 && row.has_valid_note() && !portamento {
+                if let Some(instrument) = self.module.get_instrument(row.instrument as usize - 1) {
+                    if let Some(sample) = instrument.get_note_sample_ref(row.note as usize - 1) {
                         self.instrument = Some(instrument);
                         self.sample = Some(sample);
                     }
@@ -293,19 +808,47 @@ impl<'a> Channel<'a> {
                     // Invalid instrument
                     self.note_kill();
                 }
+            } else if row.has_valid_note() && !portamento {
+                // Note without instrument picks its sample from the current instrument
+                if let Some(instrument) = &self.instrument {
+                    if let Some(sample) = instrument.get_note_sample_ref(row.note as usize - 1) {
+                        self.sample = Some(sample);
+                    }
+                }
+            } else if row.instrument > 0 && !row.has_valid_note() {
+                // Instrument without note resets volume & panning of the playing sample (FT2)
+                if let Some(sample) = self.sample.clone() {
+                    self.reset_volume_and_panning(&sample);
+                }
             }
 
             self.row = row;
 
-            if row.has_valid_note() && self.is_note_active() {
+            if row.has_valid_note() && portamento {
+                self.set_portamento_target();
+            } else if row.has_valid_note() && self.is_note_active() {
                 self.note_on();
             } else if row.is_note_off() {
                 self.note_off();
+            } else if row.is_note_cut() {
+                self.note_volume = 0;
             }
         }
 
-        self.apply_effects(row_tick_index);
+        self.apply_effects(row_tick_index.saturating_sub(note_delay), speed);
         self.tick_envelopes();
+        self.tick_auto_vibrato();
+        self.set_frequency(self.final_period);
+
+        self.final_volume = (self.final_volume * global_volume) / 64;
+
+        // Mixing reads sample data unchecked, so never start outside of it (empty sample,
+        // stale position after switching to shorter sample)
+        if let Some(sample) = &self.sample {
+            if self.sample_offset >= sample.sample_end {
+                self.note_kill();
+            }
+        }
 
         if let Some(sample) = self.sample.clone() {
             let mut offset = self.sample_offset;
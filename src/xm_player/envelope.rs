@@ -1,26 +1,48 @@
 #[derive(Clone, Default)]
 pub struct Envelope {
-    pub tick_values: Vec<u8>,
-    pub sustain: usize,
-    pub loop_start: usize,
-    pub loop_end: usize,
+    // Envelope points (tick, value) as stored in module, values are 0-64
+    pub points: Vec<(usize, usize)>,
+    pub enabled: bool,
+    pub sustain_enabled: bool,
+    pub loop_enabled: bool,
+    // Sustain & loop are indices into `points`
+    pub sustain_point: usize,
+    pub loop_start_point: usize,
+    pub loop_end_point: usize,
     pub fadeout: u16,
+
+    // Baked by `build`: value for every tick, sustain & loop as tick times
+    // (None when not used). Empty when envelope is not enabled.
+    pub tick_values: Vec<u8>,
+    pub sustain_tick: Option<usize>,
+    pub loop_start_tick: Option<usize>,
+    pub loop_end_tick: Option<usize>,
 }
 
 impl Envelope {
-    pub fn build(&mut self, points: &[usize], enable_sustain: bool, enable_loop: bool) {
+    // Envelope position of a new note, one tick before the first point. Position is
+    // advanced before reading the value, so the first tick shows the first point.
+    pub const START_TICK: usize = usize::MAX;
+
+    // Rebuilds tick table from points, has to be called after points or flags change
+    pub fn build(&mut self) {
         self.tick_values.clear();
+        self.sustain_tick = None;
+        self.loop_start_tick = None;
+        self.loop_end_tick = None;
 
-        if points.len() < 4 || (points.len() % 2) != 0 {
+        if !self.enabled || self.points.len() < 2 {
             return;
         }
 
-        let mut prev_tick = points[0] as i32;
-        let mut prev_value = points[1] as i32;
+        let (first_tick, first_value) = self.points[0];
+        let mut prev_tick = first_tick as i32;
+        // Envelope values are 0-64, anything above is garbage (e.g. from disabled envelope)
+        let mut prev_value = first_value.min(64) as i32;
 
-        for pi in (2..points.len() - 1).step_by(2) {
-            let to_tick = points[pi] as i32;
-            let to_value = points[pi + 1] as i32;
+        for &(tick, value) in &self.points[1..] {
+            let to_tick = tick as i32;
+            let to_value = value.min(64) as i32;
             let num_ticks = to_tick - prev_tick;
 
             for ti in 0..num_ticks {
@@ -35,34 +57,53 @@ impl Envelope {
         // Ensure last envelope point value is stored as well
         self.tick_values.push(prev_value as u8);
 
-        // Convert sustain to tick time
-        if (self.sustain as usize) < (points.len() / 2) {
-            self.sustain = points[(self.sustain * 2) as usize] as usize;
-        } else {
-            self.sustain = usize::MAX;
+        let point_tick = |index: usize| self.points.get(index).map(|p| p.0);
+
+        if self.sustain_enabled {
+            self.sustain_tick = point_tick(self.sustain_point);
         }
 
-        // Convert loop_start to tick time
-        if (self.loop_start as usize) < (points.len() / 2) {
-            self.loop_start = points[(self.loop_start * 2) as usize] as usize;
-        } else {
-            self.loop_start = usize::MAX;
+        // Loop is only used when both ends point to existing points
+        if self.loop_enabled {
+            if let (Some(start), Some(end)) = (
+                point_tick(self.loop_start_point),
+                point_tick(self.loop_end_point),
+            ) {
+                self.loop_start_tick = Some(start);
+                self.loop_end_tick = Some(end);
+            }
         }
+    }
 
-        // Convert loop_end to tick time
-        if (self.loop_end as usize) < (points.len() / 2) {
-            self.loop_end = points[(self.loop_end * 2) as usize] as usize;
-        } else {
-            self.loop_end = usize::MAX;
+    // Advances envelope position by one tick, same as FT2. Sustain point holds the
+    // envelope while note is held. Reaching loop end jumps back to loop start, held or
+    // not, unless loop end is also the sustain point the note is held at.
+    pub fn follow(&self, ticks: usize, note_released: bool) -> usize {
+        if self.tick_values.is_empty() {
+            return ticks;
+        }
+
+        let held = |ticks| !note_released && self.sustain_tick == Some(ticks);
+
+        if held(ticks) {
+            return ticks;
         }
 
-        if !enable_sustain {
-            self.sustain = usize::MAX;
+        let ticks = ticks.wrapping_add(1);
+
+        match self.loop_start_tick {
+            Some(loop_start) if self.loop_end_tick == Some(ticks) && !held(ticks) => loop_start,
+            _ => ticks,
         }
+    }
 
-        if !enable_loop {
-            self.loop_start = usize::MAX;
-            self.loop_end = usize::MAX;
+    // Envelope position after key-off. When held at sustain point, FT2 steps one tick
+    // back, so the point is reached again on next tick (and loop end jumps to loop start).
+    pub fn release(&self, ticks: usize) -> usize {
+        if self.sustain_tick == Some(ticks) {
+            ticks.wrapping_sub(1)
+        } else {
+            ticks
         }
     }
 
@@ -0,0 +1,886 @@
+use std::rc::Rc;
+
+use super::module::read_header_field;
+use super::module::single_sample_instrument;
+use super::scream_tracker;
+use super::BinaryReader;
+use super::DuplicateCheckType;
+use super::EndOfData;
+use super::Envelope;
+use super::Instrument;
+use super::LoadError;
+use super::LoopType;
+use super::Module;
+use super::NewNoteAction;
+use super::Pattern;
+use super::Row;
+use super::Sample;
+
+pub(super) const SIGNATURE: &[u8] = b"IMPM";
+
+// IT has 120 notes starting from C-0, its C-5 plays samples at their middle C frequency
+// like XM C-4 does, so IT notes are 11 above 1-based XM notes
+const NOTE_OFFSET: usize = 11;
+
+// Instruments from trackers older than 2.00 have different header layout
+const NEW_INSTRUMENT_FORMAT_VERSION: u16 = 0x200;
+
+// Auto-vibrato is stored with every sample in IT, but XM has it per instrument
+#[derive(Clone, Copy, Default)]
+struct SampleVibrato {
+    vibrato_type: u8,
+    sweep: u8,
+    depth: u8,
+    rate: u8,
+}
+
+impl SampleVibrato {
+    fn apply(&self, instrument: &mut Instrument) {
+        // IT: sine, ramp down, square, random. XM has no random waveform.
+        instrument.vibrato_type = match self.vibrato_type {
+            1 => 2,
+            2 => 1,
+            _ => 0,
+        };
+        instrument.vibrato_sweep = self.sweep;
+        instrument.vibrato_depth = self.depth;
+        instrument.vibrato_rate = self.rate;
+    }
+}
+
+fn get_new_note_action(value: u8) -> NewNoteAction {
+    match value {
+        1 => NewNoteAction::Continue,
+        2 => NewNoteAction::NoteOff,
+        3 => NewNoteAction::NoteFade,
+        _ => NewNoteAction::Cut,
+    }
+}
+
+// Converts IT note to XM note. Note cut stays note cut, note off and note fade become
+// key-off, which also releases sustain of envelopes.
+fn convert_note(note: u8) -> u8 {
+    match note {
+        0..=119 => (note as usize).saturating_sub(NOTE_OFFSET).clamp(1, 96) as u8,
+        254 => 98,
+        _ => 97,
+    }
+}
+
+// Converts IT volume column to XM volume column
+fn convert_volume(volume: u8) -> u8 {
+    match volume {
+        // Set volume
+        0..=64 => 0x10 + volume,
+        // Fine volume slide up & down
+        65..=74 => 0x90 | (volume - 65),
+        75..=84 => 0x80 | (volume - 75),
+        // Volume slide up & down
+        85..=94 => 0x70 | (volume - 85),
+        95..=104 => 0x60 | (volume - 95),
+        // Set panning, 0-64
+        128..=192 => 0xC0 | ((volume - 128) / 4).min(15),
+        // Tone portamento, parameter is index to speed table
+        193..=202 => {
+            const SPEEDS: [u8; 10] = [0, 1, 4, 8, 16, 32, 64, 96, 128, 255];
+            0xF0 | SPEEDS[(volume - 193) as usize] >> 4
+        }
+        // Vibrato depth
+        203..=212 => 0xB0 | (volume - 203),
+        // Pitch slides have no volume column counterpart in XM
+        _ => 0,
+    }
+}
+
+// Converts IT effect (A = 1, B = 2, ...) to XM effect type & parameter. Most effects are
+// the same as in S3M.
+fn convert_effect(effect: u8, param: u8, order_remap: &[usize]) -> (u8, u8) {
+    let x = param >> 4;
+    let y = param & 0x0F;
+
+    if !(1..=26).contains(&effect) {
+        return (0, 0);
+    }
+
+    match effect + b'A' - 1 {
+        // Panning slide, left & right are swapped compared to XM. No fine slides in XM.
+        b'P' if (x == 0x0F && y != 0) || (y == 0x0F && x != 0) => (0, 0),
+        b'P' => (0x19, (y << 4) | x),
+        // Set global volume, 0-128
+        b'V' => (0x10, param.min(0x80) / 2),
+        // Global volume slide, without fine slides. Steps are halved like volume in Vxx,
+        // rounding up so that slides never stop.
+        b'W' if (x == 0x0F && y != 0) || (y == 0x0F && x != 0) => (0, 0),
+        b'W' => (0x11, (x.div_ceil(2) << 4) | y.div_ceil(2)),
+        // Set panning, full 0-255 range
+        b'X' => (0x08, param),
+        _ => scream_tracker::convert_effect(effect, param, order_remap),
+    }
+}
+
+impl Module {
+    pub(super) fn parse_it(data: &[u8], lenient: bool) -> Result<Module, LoadError> {
+        if !data.starts_with(SIGNATURE) {
+            return Err(LoadError::BadMagic { offset: 0 });
+        }
+
+        let mut br = BinaryReader::new(data);
+        let truncated = |e: EndOfData| LoadError::TruncatedHeader { offset: e.offset };
+
+        br.pos = SIGNATURE.len();
+
+        let mut result = Module {
+            name: br
+                .read_string_segment(26)
+                .map_err(truncated)?
+                .trim()
+                .to_string(),
+            ..Module::default()
+        };
+
+        // Pattern row highlight
+        br.pos += 2;
+
+        let num_orders = read_header_field(&mut br, "song length", 1..=256)?;
+        let num_instruments = read_header_field(&mut br, "number of instruments", 0..=255)?;
+        let num_samples = read_header_field(&mut br, "number of samples", 0..=255)?;
+        let num_patterns = read_header_field(&mut br, "number of patterns", 0..=256)?;
+
+        let tracker_version = br.read_u16().map_err(truncated)?;
+        result.version = tracker_version as i32;
+        result.tracker = match tracker_version >> 12 {
+            0 => "Impulse Tracker",
+            1 => "Schism Tracker",
+            5 => "OpenMPT",
+            _ => "Unknown",
+        }
+        .to_string();
+
+        let compatible_version = br.read_u16().map_err(truncated)?;
+        let flags = br.read_u16().map_err(truncated)?;
+
+        let stereo = (flags & 1) != 0;
+        let use_instruments = (flags & 4) != 0;
+        result.linear_freq_table = (flags & 8) != 0;
+
+        // Special flags
+        br.pos += 2;
+
+        // Global volume is 0-128
+        result.global_volume = (br.read_u8().map_err(truncated)?.min(128) / 2) as usize;
+
+        // Mix volume
+        br.pos += 1;
+
+        result.tempo = br.read_u8().map_err(truncated)?.max(1) as usize;
+        result.bpm = br.read_u8().map_err(truncated)?.max(32) as usize;
+
+        // Panning separation, pitch wheel depth, song message and reserved
+        br.pos += 12;
+
+        // Panning 0-64, 100 is surround, channels with top bit set are disabled
+        let mut channel_enabled = [false; 64];
+        let mut channel_panning = [128; 64];
+        for i in 0..64 {
+            let panning = br.read_u8().map_err(truncated)?;
+
+            channel_enabled[i] = (panning & 0x80) == 0;
+            if stereo && panning <= 64 {
+                channel_panning[i] = (panning as usize * 255 / 64) as u8;
+            }
+        }
+
+        // Channel volume
+        br.pos += 64;
+
+        let order_remap = result.read_pattern_order(&mut br, num_orders)?;
+
+        let mut read_pointers = |count: usize| -> Result<Vec<usize>, LoadError> {
+            let mut pointers = Vec::with_capacity(count);
+            for _ in 0..count {
+                pointers.push(br.read_u32().map_err(truncated)? as usize);
+            }
+
+            Ok(pointers)
+        };
+
+        let instrument_pointers = read_pointers(num_instruments)?;
+        let sample_pointers = read_pointers(num_samples)?;
+        let pattern_pointers = read_pointers(num_patterns)?;
+
+        // Pattern order may point to patterns, which do not exist
+        let num_patterns = result
+            .pattern_order
+            .iter()
+            .fold(num_patterns, |n, &pattern_index| n.max(pattern_index + 1));
+
+        // Patterns are parsed with all 64 channels, and cut down to channels actually
+        // used afterwards. Data is reached through pointers, so truncated pattern does
+        // not stop loading the rest.
+        let mut num_used_channels = 1;
+        let mut pattern_errors = Vec::new();
+        result.patterns.resize(num_patterns, Pattern::default());
+        for (pattern_index, pattern) in result.patterns.iter_mut().enumerate() {
+            pattern.channels.resize(64, Vec::new());
+            pattern.resize(64);
+
+            // Zero pointer means empty pattern
+            let pointer = pattern_pointers.get(pattern_index).copied().unwrap_or(0);
+            if pointer == 0 {
+                continue;
+            }
+
+            br.pos = pointer;
+            if let Err(err) = pattern.parse_it(
+                &mut br,
+                pattern_index,
+                &channel_enabled,
+                &order_remap,
+                &mut num_used_channels,
+            ) {
+                pattern_errors.push(err);
+            }
+        }
+
+        result.num_channels = num_used_channels;
+        result.channel_panning = channel_panning[..num_used_channels].to_vec();
+        for pattern in &mut result.patterns {
+            pattern.channels.truncate(num_used_channels);
+        }
+
+        for err in pattern_errors {
+            result.recover(err, lenient)?;
+        }
+
+        let mut samples = Vec::with_capacity(num_samples);
+        let mut sample_vibratos = Vec::with_capacity(num_samples);
+        for (i, &pointer) in sample_pointers.iter().enumerate() {
+            br.pos = pointer;
+
+            let mut sample = Sample::default();
+            let mut vibrato = SampleVibrato::default();
+            if let Err(err) = sample.parse_it(&mut br, i, &mut vibrato) {
+                result.recover(err, lenient)?;
+            }
+
+            samples.push(Rc::new(sample));
+            sample_vibratos.push(vibrato);
+        }
+
+        if use_instruments {
+            result.num_instruments = num_instruments;
+            for (i, &pointer) in instrument_pointers.iter().enumerate() {
+                br.pos = pointer;
+
+                let mut instrument = Instrument::default();
+                if let Err(err) =
+                    instrument.parse_it(&mut br, i, compatible_version, &samples, &sample_vibratos)
+                {
+                    result.recover(err, lenient)?;
+                }
+
+                result.instruments.push(Rc::new(instrument));
+            }
+        } else {
+            // Sample mode, every sample becomes an instrument with just that single sample
+            result.num_instruments = num_samples;
+            for (sample, vibrato) in samples.into_iter().zip(sample_vibratos) {
+                let mut instrument = single_sample_instrument(sample);
+
+                vibrato.apply(&mut instrument);
+                result.instruments.push(Rc::new(instrument));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Pattern {
+    fn parse_it(
+        &mut self,
+        br: &mut BinaryReader,
+        pattern_index: usize,
+        channel_enabled: &[bool; 64],
+        order_remap: &[usize],
+        num_used_channels: &mut usize,
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedPattern {
+            offset: e.offset,
+            pattern: pattern_index,
+        };
+
+        // Packed data length
+        br.read_u16().map_err(truncated)?;
+
+        let num_rows_pos = br.pos;
+        let num_rows = br.read_u16().map_err(truncated)? as usize;
+        if num_rows == 0 || num_rows > 256 {
+            return Err(LoadError::InvalidPattern {
+                offset: num_rows_pos,
+                pattern: pattern_index,
+            });
+        }
+
+        self.resize(num_rows);
+
+        // Reserved
+        br.pos += 4;
+
+        // Every channel remembers its last mask and values, which can be reused by
+        // following rows without storing them again
+        let mut last_mask = [0u8; 64];
+        let mut last_row = [Row::default(); 64];
+
+        let mut line = 0;
+        while line < num_rows {
+            let channel_variable = br.read_u8().map_err(truncated)?;
+
+            // End of row
+            if channel_variable == 0 {
+                line += 1;
+                continue;
+            }
+
+            let channel = ((channel_variable - 1) & 63) as usize;
+            if (channel_variable & 0x80) != 0 {
+                last_mask[channel] = br.read_u8().map_err(truncated)?;
+            }
+
+            let mask = last_mask[channel];
+            let last = &mut last_row[channel];
+            let mut row = Row::default();
+
+            if (mask & 0x01) != 0 {
+                last.note = convert_note(br.read_u8().map_err(truncated)?);
+                row.note = last.note;
+            }
+
+            if (mask & 0x02) != 0 {
+                last.instrument = br.read_u8().map_err(truncated)?;
+                row.instrument = last.instrument;
+            }
+
+            if (mask & 0x04) != 0 {
+                last.volume = convert_volume(br.read_u8().map_err(truncated)?);
+                row.volume = last.volume;
+            }
+
+            if (mask & 0x08) != 0 {
+                let effect = br.read_u8().map_err(truncated)?;
+                let param = br.read_u8().map_err(truncated)?;
+                (last.effect_type, last.effect_param) = convert_effect(effect, param, order_remap);
+                row.effect_type = last.effect_type;
+                row.effect_param = last.effect_param;
+            }
+
+            if (mask & 0x10) != 0 {
+                row.note = last.note;
+            }
+
+            if (mask & 0x20) != 0 {
+                row.instrument = last.instrument;
+            }
+
+            if (mask & 0x40) != 0 {
+                row.volume = last.volume;
+            }
+
+            if (mask & 0x80) != 0 {
+                row.effect_type = last.effect_type;
+                row.effect_param = last.effect_param;
+            }
+
+            // Disabled channels are muted
+            if channel_enabled[channel] {
+                self.channels[channel][line] = row;
+                *num_used_channels = (*num_used_channels).max(channel + 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Instrument {
+    fn parse_it(
+        &mut self,
+        br: &mut BinaryReader,
+        instrument_index: usize,
+        compatible_version: u16,
+        samples: &[Rc<Sample>],
+        sample_vibratos: &[SampleVibrato],
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedInstrument {
+            offset: e.offset,
+            instrument: instrument_index,
+        };
+
+        // "IMPI" signature & DOS file name
+        br.pos += 17;
+
+        let old_format = compatible_version < NEW_INSTRUMENT_FORMAT_VERSION;
+
+        if old_format {
+            let flags = br.read_u8().map_err(truncated)?;
+            self.volume_envelope.enabled = (flags & 1) != 0;
+            self.volume_envelope.loop_enabled = (flags & 2) != 0;
+            self.volume_envelope.sustain_enabled = (flags & 4) != 0;
+
+            self.volume_envelope.loop_start_point = br.read_u8().map_err(truncated)? as usize;
+            self.volume_envelope.loop_end_point = br.read_u8().map_err(truncated)? as usize;
+            self.volume_envelope.sustain_point = br.read_u8().map_err(truncated)? as usize;
+
+            // Sustain loop end, XM only has a single sustain point
+            br.pos += 3;
+
+            // Fadeout is 0-64, counting down from 512
+            self.volume_envelope.fadeout = br.read_u16().map_err(truncated)?.min(64) * 128;
+            self.new_note_action = get_new_note_action(br.read_u8().map_err(truncated)?);
+
+            // Duplicate note check is either off or cuts duplicate notes
+            if br.read_u8().map_err(truncated)? != 0 {
+                self.duplicate_check_type = DuplicateCheckType::Note;
+                self.duplicate_check_action = NewNoteAction::Cut;
+            }
+
+            // Tracker version, number of samples & reserved
+            br.pos += 4;
+        } else {
+            self.new_note_action = get_new_note_action(br.read_u8().map_err(truncated)?);
+            self.duplicate_check_type = match br.read_u8().map_err(truncated)? {
+                1 => DuplicateCheckType::Note,
+                2 => DuplicateCheckType::Sample,
+                3 => DuplicateCheckType::Instrument,
+                _ => DuplicateCheckType::Off,
+            };
+            self.duplicate_check_action = match br.read_u8().map_err(truncated)? {
+                1 => NewNoteAction::NoteOff,
+                2 => NewNoteAction::NoteFade,
+                _ => NewNoteAction::Cut,
+            };
+
+            // Fadeout is 0-1024, counting down from 1024
+            self.volume_envelope.fadeout =
+                (br.read_u16().map_err(truncated)? as u32 * 64).min(0xFFFF) as u16;
+
+            // Pitch-pan separation & center, global volume, default panning, random
+            // variations, tracker version, number of samples & reserved
+            br.pos += 10;
+        }
+
+        self.name = br
+            .read_string_segment(26)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
+
+        // Filter & MIDI settings (reserved in old format)
+        br.pos += 6;
+
+        // Keyboard table has sample for each of 120 notes. It can also transpose notes,
+        // that is not supported, so every note plays at its own pitch.
+        let mut sample_indices = vec![usize::MAX; samples.len()];
+        self.sample_keymap = vec![usize::MAX; 96];
+        for note in 0..120 {
+            // Note
+            br.read_u8().map_err(truncated)?;
+
+            let sample_number = br.read_u8().map_err(truncated)? as usize;
+            if !(NOTE_OFFSET + 1..=NOTE_OFFSET + 96).contains(&note) {
+                continue;
+            }
+
+            if sample_number == 0 || sample_number > samples.len() {
+                continue;
+            }
+
+            // Samples are shared, instrument only keeps the ones it plays
+            let sample_index = sample_number - 1;
+            if sample_indices[sample_index] == usize::MAX {
+                if self.samples.is_empty() {
+                    sample_vibratos[sample_index].apply(self);
+                }
+
+                sample_indices[sample_index] = self.samples.len();
+                self.samples.push(samples[sample_index].clone());
+            }
+
+            self.sample_keymap[note - NOTE_OFFSET - 1] = sample_indices[sample_index];
+        }
+
+        if old_format {
+            // Precalculated volume envelope, then up to 25 (tick, value) node points
+            br.pos += 200;
+
+            self.volume_envelope.points.clear();
+            for _ in 0..25 {
+                let tick = br.read_u8().map_err(truncated)?;
+                let value = br.read_u8().map_err(truncated)?;
+
+                // Node list ends with tick 0xFF
+                if tick == 0xFF {
+                    break;
+                }
+
+                self.volume_envelope
+                    .points
+                    .push((tick as usize, value as usize));
+            }
+
+            self.volume_envelope.build();
+        } else {
+            read_envelope(br, &mut self.volume_envelope, instrument_index, 0)?;
+
+            // Panning envelope is -32..32, XM has 0..64
+            read_envelope(br, &mut self.panning_envelope, instrument_index, 32)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Reads 82 byte envelope with up to 25 nodes. Loops and sustain loops are points, but
+// XM only has a single sustain point, so sustain loop start is used for it.
+fn read_envelope(
+    br: &mut BinaryReader,
+    envelope: &mut Envelope,
+    instrument_index: usize,
+    value_offset: i32,
+) -> Result<(), LoadError> {
+    let truncated = |e: EndOfData| LoadError::TruncatedInstrument {
+        offset: e.offset,
+        instrument: instrument_index,
+    };
+
+    let flags = br.read_u8().map_err(truncated)?;
+
+    let num_points_pos = br.pos;
+    let num_points = br.read_u8().map_err(truncated)? as usize;
+    if num_points > 25 {
+        return Err(LoadError::InvalidEnvelope {
+            offset: num_points_pos,
+            instrument: instrument_index,
+        });
+    }
+
+    envelope.enabled = (flags & 1) != 0;
+    envelope.loop_enabled = (flags & 2) != 0;
+    envelope.sustain_enabled = (flags & 4) != 0;
+
+    envelope.loop_start_point = br.read_u8().map_err(truncated)? as usize;
+    envelope.loop_end_point = br.read_u8().map_err(truncated)? as usize;
+    envelope.sustain_point = br.read_u8().map_err(truncated)? as usize;
+
+    // Sustain loop end
+    br.read_u8().map_err(truncated)?;
+
+    envelope.points.clear();
+    for i in 0..25 {
+        let value = br.read_i8().map_err(truncated)? as i32 + value_offset;
+        let tick = br.read_u16().map_err(truncated)? as usize;
+
+        if i < num_points {
+            envelope.points.push((tick, value.clamp(0, 64) as usize));
+        }
+    }
+
+    // Reserved
+    br.read_u8().map_err(truncated)?;
+
+    envelope.build();
+
+    Ok(())
+}
+
+impl Sample {
+    fn parse_it(
+        &mut self,
+        br: &mut BinaryReader,
+        sample_index: usize,
+        vibrato: &mut SampleVibrato,
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedSample {
+            offset: e.offset,
+            instrument: None,
+            sample: sample_index,
+        };
+
+        // "IMPS" signature, DOS file name & zero byte
+        br.pos += 17;
+
+        let global_volume = br.read_u8().map_err(truncated)?.min(64) as usize;
+        let flags = br.read_u8().map_err(truncated)?;
+
+        // XM has no global sample volume, so it's applied to default volume
+        let volume = br.read_u8().map_err(truncated)?.min(64) as usize;
+        self.volume = (volume * global_volume / 64) as u8;
+
+        self.name = br
+            .read_string_segment(26)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
+
+        let convert_flags = br.read_u8().map_err(truncated)?;
+
+        // Default panning 0-64, used when top bit is set
+        let panning = br.read_u8().map_err(truncated)?;
+        self.has_panning = (panning & 0x80) != 0;
+        self.panning = ((panning & 0x7F).min(64) as usize * 255 / 64) as u8;
+
+        let sample_length = br.read_u32().map_err(truncated)? as usize;
+        let loop_start = br.read_u32().map_err(truncated)?;
+        let loop_end = br.read_u32().map_err(truncated)?;
+
+        self.set_middle_c_frequency(br.read_u32().map_err(truncated)?);
+
+        let sustain_loop_start = br.read_u32().map_err(truncated)?;
+        let sustain_loop_end = br.read_u32().map_err(truncated)?;
+        let data_pos = br.read_u32().map_err(truncated)? as usize;
+
+        vibrato.rate = br.read_u8().map_err(truncated)?;
+        vibrato.depth = br.read_u8().map_err(truncated)?;
+        vibrato.sweep = br.read_u8().map_err(truncated)?;
+        vibrato.vibrato_type = br.read_u8().map_err(truncated)?;
+
+        // No sample data associated with the header
+        if (flags & 0x01) == 0 {
+            return Ok(());
+        }
+
+        if (flags & 0x10) != 0 {
+            self.loop_type = if (flags & 0x40) != 0 {
+                LoopType::PingPong
+            } else {
+                LoopType::Forward
+            };
+            self.loop_start = loop_start as f32;
+            self.loop_end = loop_end as f32;
+        }
+
+        if (flags & 0x20) != 0 {
+            self.sustain_loop_type = if (flags & 0x80) != 0 {
+                LoopType::PingPong
+            } else {
+                LoopType::Forward
+            };
+            self.sustain_loop_start = sustain_loop_start as f32;
+            self.sustain_loop_end = sustain_loop_end as f32;
+        }
+
+        self.is_16bit = (flags & 0x02) != 0;
+        self.set_length(sample_length);
+
+        // Stereo samples store left channel first, only that one is used
+        br.pos = data_pos;
+        let complete = if (flags & 0x08) != 0 {
+            // Delta flag means IT 2.15 compression, which integrates twice
+            self.read_it_compressed_data(br, sample_length, (convert_flags & 0x04) != 0)
+        } else {
+            self.read_it_data(br, sample_length, convert_flags)
+        };
+
+        if !complete {
+            let offset = br.pos;
+            self.set_length(self.data.len());
+
+            return Err(LoadError::TruncatedSample {
+                offset,
+                instrument: None,
+                sample: sample_index,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Reads uncompressed PCM data, which can be signed or unsigned and optionally
+    // delta encoded. Returns false if data was truncated.
+    fn read_it_data(
+        &mut self,
+        br: &mut BinaryReader,
+        sample_length: usize,
+        convert_flags: u8,
+    ) -> bool {
+        let signed = (convert_flags & 0x01) != 0;
+        let delta = (convert_flags & 0x04) != 0;
+        let bytes_per_sample = if self.is_16bit { 2 } else { 1 };
+
+        self.data.clear();
+        self.data
+            .reserve(sample_length.min(br.data.len().saturating_sub(br.pos) / bytes_per_sample));
+
+        let mut acc: i16 = 0;
+        while self.data.len() < sample_length {
+            let value = if self.is_16bit {
+                match br.read_u16() {
+                    Ok(value) if signed => value as i16,
+                    Ok(value) => (value ^ 0x8000) as i16,
+                    Err(_) => return false,
+                }
+            } else {
+                match br.read_u8() {
+                    Ok(value) if signed => value as i8,
+                    Ok(value) => (value ^ 0x80) as i8,
+                    Err(_) => return false,
+                }
+                .into()
+            };
+
+            if delta {
+                acc = if self.is_16bit {
+                    acc.wrapping_add(value)
+                } else {
+                    (acc as i8).wrapping_add(value as i8).into()
+                };
+            } else {
+                acc = value;
+            }
+
+            self.data.push(if self.is_16bit { acc } else { acc * 16 });
+        }
+
+        true
+    }
+
+    // IT 2.14 compression: data is split to blocks of 0x8000 (8-bit) or 0x4000 (16-bit)
+    // samples, each block is a bitstream of delta values with variable bit width.
+    // IT 2.15 integrates the deltas once more. Returns false if data was truncated.
+    fn read_it_compressed_data(
+        &mut self,
+        br: &mut BinaryReader,
+        sample_length: usize,
+        it215: bool,
+    ) -> bool {
+        let (block_length, max_width, width_change_bits) = if self.is_16bit {
+            (0x4000, 17, 4)
+        } else {
+            (0x8000, 9, 3)
+        };
+
+        // Deltas wrap around at sample size
+        let sample_bits = max_width - 1;
+        let wrap = |value: i32| (value << (32 - sample_bits)) >> (32 - sample_bits);
+
+        self.data.clear();
+
+        while self.data.len() < sample_length {
+            let Ok(block_size) = br.read_u16() else {
+                return false;
+            };
+
+            let Some(block) = br.data.get(br.pos..br.pos + block_size as usize) else {
+                return false;
+            };
+            br.pos += block_size as usize;
+
+            let mut bits = BitReader::new(block);
+            let block_end = self.data.len() + block_length.min(sample_length - self.data.len());
+
+            let mut width: u32 = max_width;
+            let mut delta1: i32 = 0;
+            let mut delta2: i32 = 0;
+
+            while self.data.len() < block_end {
+                let Some(value) = bits.read(width) else {
+                    break;
+                };
+
+                if width < 7 {
+                    // Method 1: lowest bits set to a single one tell new width
+                    if value == 1 << (width - 1) {
+                        let Some(new_width) = bits.read(width_change_bits) else {
+                            break;
+                        };
+
+                        let new_width = new_width + 1;
+                        width = if new_width < width {
+                            new_width
+                        } else {
+                            new_width + 1
+                        };
+                        continue;
+                    }
+                } else if width < max_width {
+                    // Method 2: values in a small range around top of width tell new width
+                    let border = ((1 << (width - 1)) - 1) - (max_width - 1) / 2;
+                    if value > border && value <= border + (max_width - 1) {
+                        let new_width = value - border;
+                        width = if new_width < width {
+                            new_width
+                        } else {
+                            new_width + 1
+                        };
+                        continue;
+                    }
+                } else if width == max_width {
+                    // Method 3: top bit set tells new width in lowest bits
+                    if (value & (1 << (max_width - 1))) != 0 {
+                        width = (value + 1) & 0xFF;
+                        if width == 0 || width > max_width {
+                            break;
+                        }
+
+                        continue;
+                    }
+                } else {
+                    break;
+                }
+
+                // Sign extend value to full sample size
+                let value_width = width.min(sample_bits);
+                let delta = ((value << (32 - value_width)) as i32) >> (32 - value_width);
+
+                delta1 = wrap(delta1 + delta);
+                delta2 = wrap(delta2 + delta1);
+
+                let value = if it215 { delta2 } else { delta1 };
+                self.data.push(if self.is_16bit {
+                    value as i16
+                } else {
+                    value as i16 * 16
+                });
+            }
+
+            // Corrupt block ends early, rest of it stays silent
+            self.data.resize(block_end, 0);
+        }
+
+        true
+    }
+}
+
+// Reads bits from compressed sample block, least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn read(&mut self, width: u32) -> Option<u32> {
+        let mut value = 0;
+
+        for i in 0..width {
+            let byte = *self.data.get(self.pos)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+
+        Some(value)
+    }
+}
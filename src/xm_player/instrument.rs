@@ -1,10 +1,34 @@
-use std::error;
+use std::io;
 use std::rc::Rc;
 
+use super::module::check_xm_field;
 use super::BinaryReader;
+use super::BinaryWriter;
+use super::EndOfData;
 use super::Envelope;
+use super::LoadError;
 use super::Sample;
 
+// What happens to playing note, when new note starts on the same channel (IT only)
+#[derive(Clone, Copy, Default)]
+pub enum NewNoteAction {
+    #[default]
+    Cut,
+    Continue,
+    NoteOff,
+    NoteFade,
+}
+
+// Which playing notes count as duplicates of the new note (IT only)
+#[derive(Clone, Copy, Default)]
+pub enum DuplicateCheckType {
+    #[default]
+    Off,
+    Note,
+    Sample,
+    Instrument,
+}
+
 #[derive(Default)]
 pub struct Instrument {
     pub name: String,
@@ -16,32 +40,50 @@ pub struct Instrument {
     pub vibrato_sweep: u8,
     pub vibrato_depth: u8,
     pub vibrato_rate: u8,
+
+    // Impulse Tracker settings, XM instruments keep the defaults
+    pub new_note_action: NewNoteAction,
+    pub duplicate_check_type: DuplicateCheckType,
+    pub duplicate_check_action: NewNoteAction,
 }
 
 impl Instrument {
-    pub fn parse(&mut self, br: &mut BinaryReader) -> Result<(), Box<dyn error::Error>> {
-        let mut instrument_size = br.read_u32() as usize;
+    pub(crate) fn parse(
+        &mut self,
+        br: &mut BinaryReader,
+        instrument_index: usize,
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedInstrument {
+            offset: e.offset,
+            instrument: instrument_index,
+        };
+
+        let mut instrument_size = br.read_u32().map_err(truncated)? as usize;
         if instrument_size == 0 || instrument_size > 263 {
             instrument_size = 263;
         }
 
         let skip_pos = br.pos + instrument_size - 4;
 
-        self.name = br.read_string_segment(22).trim().to_string();
+        self.name = br
+            .read_string_segment(22)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
 
         // Instrument type, no meaning
-        br.read_u8();
+        br.read_u8().map_err(truncated)?;
 
-        let num_samples = br.read_u16() as usize;
+        let num_samples = br.read_u16().map_err(truncated)? as usize;
 
         self.samples.clear();
         self.sample_keymap.clear();
 
         if num_samples > 0 {
-            let _sample_header_size = br.read_u32() as usize;
+            let _sample_header_size = br.read_u32().map_err(truncated)? as usize;
 
             for _ in 0..96 {
-                let sample_index = br.read_u8() as usize;
+                let sample_index = br.read_u8().map_err(truncated)? as usize;
                 if sample_index < num_samples {
                     self.sample_keymap.push(sample_index);
                 } else {
@@ -49,68 +91,72 @@ impl Instrument {
                 }
             }
 
-            let mut volume_env_points = [0 as usize; 24];
-            let mut panning_env_points = [0 as usize; 24];
-
-            // Volume envelope points
-            for i in 0..24 {
-                volume_env_points[i] = br.read_u16() as usize;
-            }
-
-            // Panning envelope points
-            for i in 0..24 {
-                panning_env_points[i] = br.read_u16() as usize;
+            // Envelope points, 12 (tick, value) pairs each
+            for envelope in [&mut self.volume_envelope, &mut self.panning_envelope] {
+                envelope.points.clear();
+                for _ in 0..12 {
+                    let tick = br.read_u16().map_err(truncated)? as usize;
+                    let value = br.read_u16().map_err(truncated)? as usize;
+                    envelope.points.push((tick, value));
+                }
             }
 
-            let num_volume_points = br.read_u8() as usize;
-            let num_panning_points = br.read_u8() as usize;
+            // Each envelope has room for at most 12 points
+            let num_points_pos = br.pos;
+            let num_volume_points = br.read_u8().map_err(truncated)? as usize;
+            let num_panning_points = br.read_u8().map_err(truncated)? as usize;
 
-            self.volume_envelope.sustain = br.read_u8() as usize;
-            self.volume_envelope.loop_start = br.read_u8() as usize;
-            self.volume_envelope.loop_end = br.read_u8() as usize;
+            if num_volume_points > 12 || num_panning_points > 12 {
+                return Err(LoadError::InvalidEnvelope {
+                    offset: num_points_pos,
+                    instrument: instrument_index,
+                });
+            }
 
-            self.panning_envelope.sustain = br.read_u8() as usize;
-            self.panning_envelope.loop_start = br.read_u8() as usize;
-            self.panning_envelope.loop_end = br.read_u8() as usize;
+            self.volume_envelope.points.truncate(num_volume_points);
+            self.panning_envelope.points.truncate(num_panning_points);
 
-            let volume_flags = br.read_u8();
-            let panning_flags = br.read_u8();
+            for envelope in [&mut self.volume_envelope, &mut self.panning_envelope] {
+                envelope.sustain_point = br.read_u8().map_err(truncated)? as usize;
+                envelope.loop_start_point = br.read_u8().map_err(truncated)? as usize;
+                envelope.loop_end_point = br.read_u8().map_err(truncated)? as usize;
+            }
 
-            self.volume_envelope.build(
-                &volume_env_points[0..num_volume_points * 2],
-                (volume_flags & 2) != 0,
-                (volume_flags & 4) != 0,
-            );
-            self.panning_envelope.build(
-                &panning_env_points[0..num_panning_points * 2],
-                (panning_flags & 2) != 0,
-                (panning_flags & 4) != 0,
-            );
+            for envelope in [&mut self.volume_envelope, &mut self.panning_envelope] {
+                let flags = br.read_u8().map_err(truncated)?;
+                envelope.enabled = (flags & 1) != 0;
+                envelope.sustain_enabled = (flags & 2) != 0;
+                envelope.loop_enabled = (flags & 4) != 0;
+                envelope.build();
+            }
 
-            self.vibrato_type = br.read_u8();
-            self.vibrato_sweep = br.read_u8();
-            self.vibrato_depth = br.read_u8();
-            self.vibrato_rate = br.read_u8();
+            self.vibrato_type = br.read_u8().map_err(truncated)?;
+            self.vibrato_sweep = br.read_u8().map_err(truncated)?;
+            self.vibrato_depth = br.read_u8().map_err(truncated)?;
+            self.vibrato_rate = br.read_u8().map_err(truncated)?;
 
-            self.volume_envelope.fadeout = br.read_u16();
+            self.volume_envelope.fadeout = br.read_u16().map_err(truncated)?;
 
             // Reserved, unused
             br.pos += 22;
 
-            let first_sample_header_pos = br.pos;
-            let mut sample_data_pos = br.pos + num_samples * 40;
-
-            // Read all samples
+            // Read all sample headers, sample data follows right after the last header
+            let mut samples = Vec::with_capacity(num_samples);
             for i in 0..num_samples {
-                // Seek binary reader to start of sample header
-                br.pos = first_sample_header_pos + i * 40;
+                samples.push(Sample::new(br, instrument_index, i)?);
+            }
 
-                self.samples
-                    .push(Rc::new(Sample::new(br, sample_data_pos)?));
+            let mut data_result = Ok(());
+            for (i, mut sample) in samples.into_iter().enumerate() {
+                // Once sample data gets truncated, remaining samples are left empty
+                if data_result.is_ok() {
+                    data_result = sample.read_data(br, instrument_index, i);
+                }
 
-                // Current binary reader position is start of next sample data position
-                sample_data_pos = br.pos;
+                self.samples.push(Rc::new(sample));
             }
+
+            data_result?;
         } else {
             // There are no samples, so sample keymap should be full of 'None's
             for _ in 0..96 {
@@ -123,16 +169,88 @@ impl Instrument {
         Ok(())
     }
 
+    pub(crate) fn write(&self, bw: &mut BinaryWriter) -> io::Result<()> {
+        // Header without samples ends right after sample header size
+        let header_size = if self.samples.is_empty() { 33 } else { 263 };
+
+        bw.write_u32(header_size);
+        bw.write_string_segment(&self.name, 22);
+
+        // Instrument type
+        bw.write_u8(0);
+
+        let num_samples = check_xm_field("number of samples", self.samples.len(), 0..=255)?;
+        bw.write_u16(num_samples);
+
+        // Sample header size
+        bw.write_u32(40);
+
+        if self.samples.is_empty() {
+            return Ok(());
+        }
+
+        // Notes without sample point past the last sample
+        for note in 0..96 {
+            let sample_index = self.sample_keymap.get(note).copied().unwrap_or(usize::MAX);
+            bw.write_u8(sample_index.min(num_samples as usize) as u8);
+        }
+
+        // At most 12 points fit
+        let envelopes = [&self.volume_envelope, &self.panning_envelope];
+
+        for envelope in envelopes {
+            for i in 0..12 {
+                let (tick, value) = envelope.points.get(i).copied().unwrap_or((0, 0));
+                bw.write_u16(tick.min(0xFFFF) as u16);
+                bw.write_u16(value.min(0xFFFF) as u16);
+            }
+        }
+
+        for envelope in envelopes {
+            bw.write_u8(envelope.points.len().min(12) as u8);
+        }
+
+        for envelope in envelopes {
+            bw.write_u8(envelope.sustain_point.min(0xFF) as u8);
+            bw.write_u8(envelope.loop_start_point.min(0xFF) as u8);
+            bw.write_u8(envelope.loop_end_point.min(0xFF) as u8);
+        }
+
+        for envelope in envelopes {
+            bw.write_u8(
+                envelope.enabled as u8
+                    | (envelope.sustain_enabled as u8) << 1
+                    | (envelope.loop_enabled as u8) << 2,
+            );
+        }
+
+        bw.write_u8(self.vibrato_type);
+        bw.write_u8(self.vibrato_sweep);
+        bw.write_u8(self.vibrato_depth);
+        bw.write_u8(self.vibrato_rate);
+
+        bw.write_u16(self.volume_envelope.fadeout);
+
+        // Reserved
+        bw.data.resize(bw.data.len() + 22, 0);
+
+        for sample in &self.samples {
+            sample.write_header(bw);
+        }
+
+        for sample in &self.samples {
+            sample.write_data(bw);
+        }
+
+        Ok(())
+    }
+
     pub fn get_note_sample_ref(&self, note: usize) -> Option<Rc<Sample>> {
         if note >= self.sample_keymap.len() {
             None
         } else {
-            let sample_index = self.sample_keymap[note];
-            if sample_index == usize::MAX {
-                None
-            } else {
-                Some(self.samples[sample_index].clone())
-            }
+            // Sample might be missing, if instrument was only partially loaded
+            self.samples.get(self.sample_keymap[note]).cloned()
         }
     }
 }
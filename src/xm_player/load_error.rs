@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reason why a module could not be loaded. Every format variant records byte offset
+/// into the module data, where the problem was detected, and which part of the module
+/// (pattern, instrument, sample) was being parsed at that moment. Samples of formats
+/// without instruments (MOD, S3M, IT) are numbered on their own, so their errors have
+/// no instrument.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    BadMagic {
+        offset: usize,
+    },
+    TruncatedHeader {
+        offset: usize,
+    },
+    InvalidHeader {
+        offset: usize,
+        field: &'static str,
+        value: usize,
+    },
+    TruncatedPattern {
+        offset: usize,
+        pattern: usize,
+    },
+    InvalidPattern {
+        offset: usize,
+        pattern: usize,
+    },
+    TruncatedInstrument {
+        offset: usize,
+        instrument: usize,
+    },
+    InvalidEnvelope {
+        offset: usize,
+        instrument: usize,
+    },
+    TruncatedSample {
+        offset: usize,
+        instrument: Option<usize>,
+        sample: usize,
+    },
+    InvalidLoopType {
+        offset: usize,
+        instrument: Option<usize>,
+        sample: usize,
+        loop_type: u8,
+    },
+    UnsupportedCompression {
+        offset: usize,
+        instrument: Option<usize>,
+        sample: usize,
+        compression: u8,
+    },
+}
+
+impl LoadError {
+    /// Byte offset into module data, where the error was detected
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            LoadError::Io(_) => None,
+            LoadError::BadMagic { offset }
+            | LoadError::TruncatedHeader { offset }
+            | LoadError::InvalidHeader { offset, .. }
+            | LoadError::TruncatedPattern { offset, .. }
+            | LoadError::InvalidPattern { offset, .. }
+            | LoadError::TruncatedInstrument { offset, .. }
+            | LoadError::InvalidEnvelope { offset, .. }
+            | LoadError::TruncatedSample { offset, .. }
+            | LoadError::InvalidLoopType { offset, .. }
+            | LoadError::UnsupportedCompression { offset, .. } => Some(*offset),
+        }
+    }
+
+    /// Module data ended inside pattern, instrument or sample. Everything parsed up to
+    /// that point is still usable, so lenient loading turns these into warnings.
+    pub fn is_recoverable_truncation(&self) -> bool {
+        matches!(
+            self,
+            LoadError::TruncatedPattern { .. }
+                | LoadError::TruncatedInstrument { .. }
+                | LoadError::TruncatedSample { .. }
+        )
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "I/O error: {}", err),
+            LoadError::BadMagic { offset } => {
+                write!(f, "Invalid module signature at offset {:#x}", offset)
+            }
+            LoadError::TruncatedHeader { offset } => {
+                write!(f, "Module header truncated at offset {:#x}", offset)
+            }
+            LoadError::InvalidHeader {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Invalid {} {} in module header at offset {:#x}",
+                field, value, offset
+            ),
+            LoadError::TruncatedPattern { offset, pattern } => {
+                write!(f, "Pattern {} truncated at offset {:#x}", pattern, offset)
+            }
+            LoadError::InvalidPattern { offset, pattern } => write!(
+                f,
+                "Invalid data in pattern {} at offset {:#x}",
+                pattern, offset
+            ),
+            LoadError::InvalidEnvelope { offset, instrument } => write!(
+                f,
+                "Instrument {} has invalid envelope at offset {:#x}",
+                instrument, offset
+            ),
+            LoadError::TruncatedInstrument { offset, instrument } => write!(
+                f,
+                "Instrument {} truncated at offset {:#x}",
+                instrument, offset
+            ),
+            LoadError::TruncatedSample {
+                offset,
+                instrument,
+                sample,
+            } => write!(
+                f,
+                "Truncated {} at offset {:#x}",
+                SampleLocation(*instrument, *sample),
+                offset
+            ),
+            LoadError::InvalidLoopType {
+                offset,
+                instrument,
+                sample,
+                loop_type,
+            } => write!(
+                f,
+                "Invalid loop type {} in {} at offset {:#x}",
+                loop_type,
+                SampleLocation(*instrument, *sample),
+                offset
+            ),
+            LoadError::UnsupportedCompression {
+                offset,
+                instrument,
+                sample,
+                compression,
+            } => write!(
+                f,
+                "Unsupported compression {:#04x} in {} at offset {:#x}",
+                compression,
+                SampleLocation(*instrument, *sample),
+                offset
+            ),
+        }
+    }
+}
+
+// Instrument & sample numbers for messages, standalone samples only have the latter
+struct SampleLocation(Option<usize>, usize);
+
+impl fmt::Display for SampleLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(instrument) => write!(f, "instrument {}, sample {}", instrument, self.1),
+            None => write!(f, "sample {}", self.1),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
@@ -1,22 +1,28 @@
-use std::error::Error;
-use std::fmt;
+mod load_error;
+pub use load_error::LoadError;
 
 mod module;
 pub use module::Module;
 
+mod impulse_tracker;
+mod protracker;
+mod scream_tracker;
+
 mod pattern;
 pub use pattern::Pattern;
-use pattern::Row;
+pub use pattern::Row;
 
 mod envelope;
 pub use envelope::Envelope;
 
 mod instrument;
+pub use instrument::DuplicateCheckType;
 pub use instrument::Instrument;
+pub use instrument::NewNoteAction;
 
 mod sample;
-use sample::LoopType;
-use sample::Sample;
+pub use sample::LoopType;
+pub use sample::Sample;
 
 mod channel;
 pub use channel::Channel;
@@ -26,33 +32,12 @@ pub use player::Player;
 
 mod binary_reader;
 use binary_reader::BinaryReader;
+use binary_reader::EndOfData;
 
-///////////////////////////////////////////////////////////////////////////////
-
-#[derive(Debug)]
-pub struct FormatError {
-    details: String,
-}
-
-impl FormatError {
-    pub fn new(details: &str) -> FormatError {
-        FormatError {
-            details: details.to_string(),
-        }
-    }
-}
+mod binary_writer;
+use binary_writer::BinaryWriter;
 
-impl Error for FormatError {
-    fn description(&self) -> &str {
-        &self.details
-    }
-}
-
-impl fmt::Display for FormatError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
-    }
-}
+///////////////////////////////////////////////////////////////////////////////
 
 trait BitTest<T> {
     fn test_bitmask(&self, mask: T) -> bool;
@@ -1,15 +1,26 @@
-use std::error;
 use std::fs::File;
+use std::io;
 use std::io::Read;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
 use std::rc::Rc;
 
+use super::impulse_tracker;
+use super::scream_tracker;
 use super::BinaryReader;
-use super::FormatError;
+use super::BinaryWriter;
+use super::EndOfData;
 use super::Instrument;
+use super::LoadError;
 use super::Pattern;
 use super::Row;
+use super::Sample;
+
+// S3M & IT pattern order markers
+const ORDER_SKIP: u8 = 254;
+const ORDER_END: u8 = 255;
 
-#[derive(Default)]
 pub struct Module {
     pub name: String,
     pub tracker: String,
@@ -20,39 +31,118 @@ pub struct Module {
     pub restart_position: usize,
     pub num_instruments: usize,
     pub num_channels: usize,
+    // Initial panning of each channel, for formats without sample panning
+    pub channel_panning: Vec<u8>,
     pub linear_freq_table: bool,
     pub tempo: usize,
     pub bpm: usize,
+    // Initial global volume 0-64, XM always starts at full volume
+    pub global_volume: usize,
+
+    // Recoverable problems found while loading in lenient mode
+    pub warnings: Vec<LoadError>,
+}
+
+impl Default for Module {
+    fn default() -> Self {
+        Module {
+            name: String::new(),
+            tracker: String::new(),
+            version: 0,
+            patterns: Vec::new(),
+            pattern_order: Vec::new(),
+            instruments: Vec::new(),
+            restart_position: 0,
+            num_instruments: 0,
+            num_channels: 0,
+            channel_panning: Vec::new(),
+            linear_freq_table: false,
+            tempo: 0,
+            bpm: 0,
+            global_volume: 64,
+            warnings: Vec::new(),
+        }
+    }
 }
 
 impl Module {
-    pub fn load(path: &str) -> Result<Module, Box<dyn error::Error>> {
-        let mut file = File::open(path)?;
-        let mut _data = Vec::new();
-        file.read_to_end(&mut _data)?;
+    /// Loads module from a file on disk
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Module, LoadError> {
+        Module::from_reader(File::open(path)?)
+    }
+
+    /// Loads module from any readable source (archive entry, network stream, ...)
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Module, LoadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        Module::from_bytes(&data)
+    }
 
-        let data = _data;
-        let mut br = BinaryReader::new(&data);
+    /// Loads module from a complete in-memory image of the file
+    pub fn from_bytes(data: &[u8]) -> Result<Module, LoadError> {
+        Module::parse(data, false)
+    }
+
+    /// Same as `from_bytes`, but module data ending too early inside patterns, instruments
+    /// or samples is not an error. Everything up to that point is kept and the truncation
+    /// is recorded in `warnings`, so damaged modules can still be played.
+    ///
+    /// XM stores instruments after all patterns, so when data ends inside a pattern, the
+    /// module has no instruments at all, and instruments after a truncated one are
+    /// missing. `instruments` may then be shorter than `num_instruments`.
+    pub fn from_bytes_lenient(data: &[u8]) -> Result<Module, LoadError> {
+        Module::parse(data, true)
+    }
+
+    fn parse(data: &[u8], lenient: bool) -> Result<Module, LoadError> {
+        // Formats are told apart by their signatures, MOD is the one without a reliable one
+        if data.starts_with(b"Extended Module: ") {
+            Module::parse_xm(data, lenient)
+        } else if data.starts_with(impulse_tracker::SIGNATURE) {
+            Module::parse_it(data, lenient)
+        } else if data.get(scream_tracker::SIGNATURE_POS..scream_tracker::SIGNATURE_POS + 4)
+            == Some(b"SCRM")
+        {
+            Module::parse_s3m(data, lenient)
+        } else {
+            Module::parse_mod(data, lenient)
+        }
+    }
+
+    fn parse_xm(data: &[u8], lenient: bool) -> Result<Module, LoadError> {
+        let mut br = BinaryReader::new(data);
 
         // ID text
-        if br.read_string_segment(17) != "Extended Module: " {
-            return Err(Box::new(FormatError::new("Invalid ID text")));
+        let id_text = br
+            .read_string_segment(17)
+            .map_err(|_| LoadError::BadMagic { offset: 0 })?;
+
+        if id_text != "Extended Module: " {
+            return Err(LoadError::BadMagic { offset: 0 });
         }
 
         let mut result = Module::default();
 
-        result.parse_header(&mut br)?;
+        let num_patterns = result.parse_header(&mut br)?;
 
-        let mut pattern_index = 0;
-        for pattern in &mut result.patterns {
-            (*pattern).parse(&mut br)?;
+        if let Err(err) = result.parse_patterns(&mut br, num_patterns) {
+            result.recover(err, lenient)?;
 
-            pattern_index += 1;
+            // Instruments follow the patterns, so there's nothing left to read them from
+            return Ok(result);
         }
 
-        for _ in 0..result.num_instruments {
+        for instrument_index in 0..result.num_instruments {
             let mut instrument = Instrument::default();
-            instrument.parse(&mut br)?;
+
+            if let Err(err) = instrument.parse(&mut br, instrument_index) {
+                result.recover(err, lenient)?;
+
+                // Keep whatever was loaded from the last instrument
+                result.instruments.push(Rc::new(instrument));
+                break;
+            }
 
             result.instruments.push(Rc::new(instrument));
         }
@@ -60,6 +150,126 @@ impl Module {
         Ok(result)
     }
 
+    fn parse_patterns(
+        &mut self,
+        br: &mut BinaryReader,
+        num_patterns: usize,
+    ) -> Result<(), LoadError> {
+        for pattern_index in 0..num_patterns {
+            if let Err(err) = self.patterns[pattern_index].parse(br, pattern_index) {
+                // Patterns which were not reached at all are left empty
+                for pattern in &mut self.patterns[pattern_index + 1..num_patterns] {
+                    pattern.resize(64);
+                }
+
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes module in FastTracker 2 XM format. Modules loaded from other formats are
+    /// converted on the way, features XM does not have are lost.
+    pub fn save_xm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut bw = BinaryWriter::new();
+
+        bw.write_string_segment("Extended Module: ", 17);
+        bw.write_string_segment(&self.name, 20);
+        bw.write_u8(0x1A);
+        bw.write_string_segment(&self.tracker, 20);
+
+        // Only version 1.04 is written, regardless of what was loaded
+        bw.write_u16(0x0104);
+
+        // Header size, counted from its own field
+        bw.write_u32(276);
+
+        bw.write_u16(check_xm_field(
+            "song length",
+            self.pattern_order.len(),
+            1..=256,
+        )?);
+        bw.write_u16(self.restart_position.min(0xFFFF) as u16);
+        bw.write_u16(check_xm_field(
+            "number of channels",
+            self.num_channels,
+            1..=128,
+        )?);
+        bw.write_u16(check_xm_field(
+            "number of patterns",
+            self.patterns.len(),
+            0..=256,
+        )?);
+        bw.write_u16(check_xm_field(
+            "number of instruments",
+            self.instruments.len(),
+            0..=255,
+        )?);
+        bw.write_u16(self.linear_freq_table as u16);
+        bw.write_u16(check_xm_field("tempo", self.tempo, 1..=255)?);
+        bw.write_u16(check_xm_field("BPM", self.bpm, 1..=255)?);
+
+        for i in 0..256 {
+            let pattern_index = self.pattern_order.get(i).copied().unwrap_or(0);
+            bw.write_u8(check_xm_field("pattern order", pattern_index, 0..=255)? as u8);
+        }
+
+        for pattern in &self.patterns {
+            pattern.write(&mut bw, self.num_channels)?;
+        }
+
+        for instrument in &self.instruments {
+            instrument.write(&mut bw)?;
+        }
+
+        writer.write_all(&bw.data)
+    }
+
+    // In lenient mode, truncated module data is only a warning
+    pub(super) fn recover(&mut self, err: LoadError, lenient: bool) -> Result<(), LoadError> {
+        if lenient && err.is_recoverable_truncation() {
+            self.warnings.push(err);
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    // Reads S3M & IT pattern order. Skip markers are removed and song ends at first end
+    // marker, so returns new pattern order index for every stored one, which position
+    // jumps need to be remapped with.
+    pub(super) fn read_pattern_order(
+        &mut self,
+        br: &mut BinaryReader,
+        num_orders: usize,
+    ) -> Result<Vec<usize>, LoadError> {
+        let mut order_remap = Vec::with_capacity(num_orders);
+        let mut song_ended = false;
+        for _ in 0..num_orders {
+            let pattern_index = br
+                .read_u8()
+                .map_err(|e| LoadError::TruncatedHeader { offset: e.offset })?;
+            order_remap.push(self.pattern_order.len());
+
+            if pattern_index == ORDER_END {
+                song_ended = true;
+            } else if pattern_index != ORDER_SKIP && !song_ended {
+                self.pattern_order.push(pattern_index as usize);
+            }
+        }
+
+        if self.pattern_order.is_empty() {
+            return Err(LoadError::InvalidHeader {
+                offset: br.pos - num_orders,
+                field: "song length",
+                value: 0,
+            });
+        }
+
+        Ok(order_remap)
+    }
+
     pub fn get_instrument(&self, index: usize) -> Option<Rc<Instrument>> {
         if index < self.instruments.len() {
             Some(self.instruments[index].clone())
@@ -68,6 +278,19 @@ impl Module {
         }
     }
 
+    pub fn get_channel_panning(&self, channel_index: usize) -> u8 {
+        self.channel_panning
+            .get(channel_index)
+            .copied()
+            .unwrap_or(128)
+    }
+
+    pub fn get_pattern_ordered(&self, pattern_order_index: usize) -> Option<&Pattern> {
+        self.pattern_order
+            .get(pattern_order_index)
+            .and_then(|&pattern_index| self.patterns.get(pattern_index))
+    }
+
     pub fn get_channel_row_ordered(
         &self,
         pattern_order_index: usize,
@@ -86,54 +309,135 @@ impl Module {
         self.patterns[pattern_index].get_channel_row(channel_index, row_index)
     }
 
-    fn parse_header(&mut self, br: &mut BinaryReader) -> Result<(), Box<dyn error::Error>> {
+    // Returns number of patterns stored in the module
+    fn parse_header(&mut self, br: &mut BinaryReader) -> Result<usize, LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedHeader { offset: e.offset };
+
         // Module name
-        self.name = br.read_string_segment(20).trim().to_string();
+        self.name = br
+            .read_string_segment(20)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
 
         // 0x1A separator
-        if br.read_u8() != 0x1A {
-            return Err(Box::new(FormatError::new("Invalid XM header")));
+        let separator_pos = br.pos;
+        if br.read_u8().map_err(truncated)? != 0x1A {
+            return Err(LoadError::BadMagic {
+                offset: separator_pos,
+            });
         }
 
         // Tracker name
-        self.tracker = br.read_string_segment(20).trim().to_string();
+        self.tracker = br
+            .read_string_segment(20)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
 
         // Module version
-        self.version = br.read_u16() as i32;
+        self.version = br.read_u16().map_err(truncated)? as i32;
 
-        let header_size = br.read_u32() as usize;
+        let header_size = br.read_u32().map_err(truncated)? as usize;
 
-        let song_length = br.read_u16() as usize;
+        let song_length = read_header_field(br, "song length", 1..=256)?;
         self.pattern_order.resize(song_length, 0);
 
-        // Restart position index
-        self.restart_position = br.read_u16() as usize;
+        // Restart position index, FT2 restarts from beginning when it's out of range
+        self.restart_position = br.read_u16().map_err(truncated)? as usize;
+        if self.restart_position >= song_length {
+            self.restart_position = 0;
+        }
 
         // Number of channels
-        self.num_channels = br.read_u16() as usize;
+        self.num_channels = read_header_field(br, "number of channels", 1..=128)?;
 
-        let num_patterns = br.read_u16() as usize;
-        self.patterns.resize(num_patterns, Pattern::default());
-        for i in 0..num_patterns {
-            self.patterns[i]
-                .channels
-                .resize(self.num_channels, Vec::new());
-        }
+        let num_patterns = read_header_field(br, "number of patterns", 0..=256)?;
 
-        self.num_instruments = br.read_u16() as usize;
+        self.num_instruments = read_header_field(br, "number of instruments", 0..=255)?;
 
         // Usage of linear frequency table
-        self.linear_freq_table = br.read_u16() == 1;
+        self.linear_freq_table = br.read_u16().map_err(truncated)? == 1;
 
         // Tempo and BPM
-        self.tempo = br.read_u16() as usize;
-        self.bpm = br.read_u16() as usize;
+        self.tempo = read_header_field(br, "tempo", 1..=255)?;
+        self.bpm = read_header_field(br, "BPM", 1..=255)?;
 
         for i in 0..song_length {
-            self.pattern_order[i] = br.read_u8() as usize;
+            self.pattern_order[i] = br.read_u8().map_err(truncated)? as usize;
         }
 
+        // Header size is counted from its own field at offset 60
         br.pos = header_size + 60;
-        Ok(())
+        if br.pos > br.data.len() {
+            return Err(LoadError::TruncatedHeader {
+                offset: br.data.len(),
+            });
+        }
+
+        // Pattern order may point to patterns, which do not exist. FT2 plays them as
+        // empty 64 row patterns.
+        let num_order_patterns = self
+            .pattern_order
+            .iter()
+            .fold(num_patterns, |n, &pattern_index| n.max(pattern_index + 1));
+
+        self.patterns.resize(num_order_patterns, Pattern::default());
+        for pattern in &mut self.patterns {
+            pattern.channels.resize(self.num_channels, Vec::new());
+        }
+        for pattern in &mut self.patterns[num_patterns..] {
+            pattern.resize(64);
+        }
+
+        Ok(num_patterns)
+    }
+}
+
+// Instrument with just a single sample, for formats where every sample is an instrument
+pub(super) fn single_sample_instrument(sample: Rc<Sample>) -> Instrument {
+    Instrument {
+        name: sample.name.clone(),
+        samples: vec![sample],
+        sample_keymap: vec![0; 96],
+        ..Instrument::default()
     }
 }
+
+// Reads 16-bit header field and checks it's in valid range
+pub(super) fn read_header_field(
+    br: &mut BinaryReader,
+    field: &'static str,
+    range: RangeInclusive<usize>,
+) -> Result<usize, LoadError> {
+    let offset = br.pos;
+    let value = br
+        .read_u16()
+        .map_err(|e| LoadError::TruncatedHeader { offset: e.offset })? as usize;
+
+    if !range.contains(&value) {
+        return Err(LoadError::InvalidHeader {
+            offset,
+            field,
+            value,
+        });
+    }
+
+    Ok(value)
+}
+
+// Checks that value fits into XM header field
+pub(super) fn check_xm_field(
+    field: &str,
+    value: usize,
+    range: RangeInclusive<usize>,
+) -> io::Result<u16> {
+    if !range.contains(&value) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} can't be stored in XM", field, value),
+        ));
+    }
+
+    Ok(value as u16)
+}
@@ -1,6 +1,7 @@
-use std::error;
+use std::io;
 
-use super::{BinaryReader, BitTest, NibbleTest};
+use super::module::check_xm_field;
+use super::{BinaryReader, BinaryWriter, BitTest, EndOfData, LoadError, NibbleTest};
 
 #[derive(Clone, Copy)]
 pub struct Row {
@@ -26,13 +27,15 @@ impl Default for Row {
 impl Row {
     pub fn to_colored_string(&self) -> String {
         if self.note >= 0x80 {
-            return format!("\x1b[30m...     ");
+            return "\x1b[30m...     ".to_string();
         } else if self.note == 96 {
-            return format!("\x1b[0;37m== .....");
+            return "\x1b[0;37m== .....".to_string();
+        } else if self.is_note_cut() {
+            return "\x1b[0;37m^^ .....".to_string();
         }
 
-        static NOTES: &'static str = "CCDDEFFGGAAB";
-        static SHARP: &'static str = "-#-#--#-#-#-";
+        static NOTES: &str = "CCDDEFFGGAAB";
+        static SHARP: &str = "-#-#--#-#-#-";
         let note_index = (self.note % 12) as usize;
         let octave = 1 + (self.note / 12) as usize;
 
@@ -87,6 +90,55 @@ impl Row {
     pub fn is_note_off(&self) -> bool {
         self.note == 97
     }
+
+    // XM has no note cut, it's only loaded from other formats
+    pub fn is_note_cut(&self) -> bool {
+        self.note == 98
+    }
+
+    // Writes row with XM packing: fully filled rows are stored as is, others get a mask
+    // byte telling which fields follow
+    fn write_packed(&self, bw: &mut BinaryWriter) {
+        // Empty note is either 0 or 0x80, depending on how the row was packed
+        let note = if (1..=97).contains(&self.note) {
+            self.note
+        } else {
+            0
+        };
+
+        // Note cut is written as key-off with zero volume, which silences the note. Volume
+        // column of the row is lost, any other value would keep the note audible.
+        let (note, volume) = if self.is_note_cut() {
+            (97, 0x10)
+        } else {
+            (note, self.volume)
+        };
+
+        let fields = [
+            note,
+            self.instrument,
+            volume,
+            self.effect_type,
+            self.effect_param,
+        ];
+
+        if fields.iter().all(|&field| field != 0) {
+            fields.iter().for_each(|&field| bw.write_u8(field));
+            return;
+        }
+
+        let mask = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, &field)| field != 0)
+            .fold(0x80, |mask, (i, _)| mask | (1 << i));
+
+        bw.write_u8(mask);
+        fields
+            .iter()
+            .filter(|&&field| field != 0)
+            .for_each(|&field| bw.write_u8(field));
+    }
 }
 
 #[derive(Clone, Default)]
@@ -96,25 +148,48 @@ pub struct Pattern {
 }
 
 impl Pattern {
-    pub fn parse(&mut self, br: &mut BinaryReader) -> Result<(), Box<dyn error::Error>> {
-        let _pattern_header_len = br.read_u32();
+    pub(crate) fn parse(
+        &mut self,
+        br: &mut BinaryReader,
+        pattern_index: usize,
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedPattern {
+            offset: e.offset,
+            pattern: pattern_index,
+        };
+
+        let _pattern_header_len = br.read_u32().map_err(truncated)?;
 
         // Packing type, not used
-        br.read_u8();
+        br.read_u8().map_err(truncated)?;
 
-        self.num_rows = br.read_u16() as usize;
-        for i in 0..self.channels.len() {
-            self.channels[i].resize(self.num_rows, Row::default());
+        let num_rows_pos = br.pos;
+        let num_rows = br.read_u16().map_err(truncated)? as usize;
+        if num_rows == 0 || num_rows > 256 {
+            return Err(LoadError::InvalidPattern {
+                offset: num_rows_pos,
+                pattern: pattern_index,
+            });
         }
 
-        let packed_data_size = br.read_u16() as usize;
+        self.resize(num_rows);
+
+        let packed_data_size = br.read_u16().map_err(truncated)? as usize;
 
         let mut i: usize = 0;
         let mut line: usize = 0;
         let mut channel: usize = 0;
 
         while i < packed_data_size {
-            let note = br.read_u8();
+            // Packed data must not run past the last row
+            if line >= self.num_rows {
+                return Err(LoadError::InvalidPattern {
+                    offset: br.pos,
+                    pattern: pattern_index,
+                });
+            }
+
+            let note = br.read_u8().map_err(truncated)?;
             i += 1;
 
             let row = &mut self.channels[channel][line];
@@ -123,37 +198,37 @@ impl Pattern {
             // Packed row item
             if note.test_bitmask(0x80) {
                 if note.test_bitmask(0b00001) {
-                    row.note = br.read_u8();
+                    row.note = br.read_u8().map_err(truncated)?;
                     i += 1;
                 }
 
                 if note.test_bitmask(0b00010) {
-                    row.instrument = br.read_u8();
+                    row.instrument = br.read_u8().map_err(truncated)?;
                     i += 1;
                 }
 
                 if note.test_bitmask(0b00100) {
-                    row.volume = br.read_u8();
+                    row.volume = br.read_u8().map_err(truncated)?;
                     i += 1;
                 }
 
                 if note.test_bitmask(0b01000) {
-                    row.effect_type = br.read_u8();
+                    row.effect_type = br.read_u8().map_err(truncated)?;
                     i += 1;
                 }
 
                 if note.test_bitmask(0b10000) {
-                    row.effect_param = br.read_u8();
+                    row.effect_param = br.read_u8().map_err(truncated)?;
                     i += 1;
                 }
             }
             // Full row item
             else {
                 row.note = note;
-                row.instrument = br.read_u8();
-                row.volume = br.read_u8();
-                row.effect_type = br.read_u8();
-                row.effect_param = br.read_u8();
+                row.instrument = br.read_u8().map_err(truncated)?;
+                row.volume = br.read_u8().map_err(truncated)?;
+                row.effect_type = br.read_u8().map_err(truncated)?;
+                row.effect_param = br.read_u8().map_err(truncated)?;
                 i += 4;
             }
 
@@ -167,6 +242,43 @@ impl Pattern {
         Ok(())
     }
 
+    pub(crate) fn write(&self, bw: &mut BinaryWriter, num_channels: usize) -> io::Result<()> {
+        let mut packed = BinaryWriter::new();
+        for line in 0..self.num_rows {
+            for channel in 0..num_channels {
+                self.get_channel_row(channel, line)
+                    .write_packed(&mut packed);
+            }
+        }
+
+        // Patterns without any data are stored with no packed data at all
+        if packed.data.iter().all(|&b| b == 0x80) {
+            packed.data.clear();
+        }
+
+        // Pattern header length, packing type
+        bw.write_u32(9);
+        bw.write_u8(0);
+
+        bw.write_u16(check_xm_field("pattern length", self.num_rows, 1..=256)?);
+        bw.write_u16(check_xm_field(
+            "packed pattern size",
+            packed.data.len(),
+            0..=0xFFFF,
+        )?);
+
+        bw.data.extend_from_slice(&packed.data);
+        Ok(())
+    }
+
+    // Sets number of rows, new rows are empty
+    pub fn resize(&mut self, num_rows: usize) {
+        self.num_rows = num_rows;
+        for rows in &mut self.channels {
+            rows.resize(num_rows, Row::default());
+        }
+    }
+
     pub fn get_channel_row(&self, channel_index: usize, row_index: usize) -> Row {
         if channel_index >= self.channels.len() {
             return Row::default();
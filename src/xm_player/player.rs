@@ -1,26 +1,51 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__m256i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_adds_epi16;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::_mm256_loadu_si256;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::_mm256_storeu_si256;
 use std::ops::BitAnd;
 use std::time::Duration;
 use std::time::Instant;
 
 use super::Channel;
 use super::Module;
+use super::NibbleTest;
+
+// Loaders allow at most this many rows in pattern
+const MAX_ROWS: usize = 256;
+
+// Slowest tempo FT2 allows, tick buffers are sized for it
+const MIN_BPM: usize = 32;
 
 pub struct Player<'a> {
     pub module: &'a Module,
     pub sample_rate: usize,
+    // Length of current tick
     pub samples_per_tick: usize,
+
+    // Ticks per row & tempo, start from module defaults and change with Fxx
+    pub speed: usize,
+    pub bpm: usize,
+
+    // Volume of the whole song 0-64, changed by Gxx & Hxy
+    global_volume: usize,
+
+    // Volume set by host application 0-64, scales the final mix
+    volume: usize,
+
     pub pattern_order_index: usize,
     pub pattern_index: usize,
     pub row_index: usize,
 
-    // Current tick inside a row, goes from 0 to module.tempo-1
+    // Current tick inside a row, goes from 0 to speed-1 (longer with pattern delay)
     pub row_tick: usize,
 
     pub num_generated_samples: usize,
 
-    // How many times song looped - might be incorrect for some
-    // modules with complicated pattern jump commands
+    // How many times song looped, i.e. returned to a row it had already played
     pub loop_count: usize,
 
     // Print colored pattern rows, while rendering/playing
@@ -28,6 +53,22 @@ pub struct Player<'a> {
 
     channels: Vec<Channel<'a>>,
 
+    // Fraction of a sample left over from previous ticks, in 1 / (2 * BPM) units
+    tick_remainder: usize,
+
+    // How many times current row is repeated (EEx)
+    pattern_delay: usize,
+
+    // Pattern loop (E6x) start row & remaining repeats for each channel
+    pattern_loop_rows: Vec<usize>,
+    pattern_loop_counts: Vec<usize>,
+
+    // Last global volume slide (Hxy) parameter of each channel
+    global_volume_slide_params: Vec<u8>,
+
+    // Rows played since last loop, 256 rows for each pattern order position
+    visited_rows: Vec<bool>,
+
     // Individual channels are rendered there each tick
     buffer: Vec<i16>,
 
@@ -44,14 +85,61 @@ pub struct Player<'a> {
     row_cpu_usage: f32,
 }
 
+// Adds channel samples to the mix, saturating at i16 range. AVX2 is used when the CPU
+// has it.
+fn mix_saturating(mix: &mut [i16], samples: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // Safe, since AVX2 support was just checked
+        unsafe { mix_saturating_avx2(mix, samples) };
+        return;
+    }
+
+    for (dst, &src) in mix.iter_mut().zip(samples) {
+        *dst = dst.saturating_add(src);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn mix_saturating_avx2(mix: &mut [i16], samples: &[i16]) {
+    let length = mix.len().min(samples.len());
+    let steps = length / 16;
+
+    let mut src = samples.as_ptr() as *const __m256i;
+    let mut dst = mix.as_mut_ptr() as *mut __m256i;
+
+    for _ in 0..steps {
+        // Buffers are only guaranteed to be aligned to i16, so use unaligned loads & stores
+        unsafe {
+            _mm256_storeu_si256(
+                dst,
+                _mm256_adds_epi16(_mm256_loadu_si256(src), _mm256_loadu_si256(dst)),
+            );
+
+            src = src.add(1);
+            dst = dst.add(1);
+        }
+    }
+
+    for i in (steps * 16)..length {
+        mix[i] = mix[i].saturating_add(samples[i]);
+    }
+}
+
 impl<'a> Player<'a> {
-    pub fn new(module: &'a Module, sample_rate: usize) -> Player {
-        let samples_per_tick = ((sample_rate * 2500) / module.bpm) / 1000;
+    pub fn new(module: &'a Module, sample_rate: usize) -> Player<'a> {
+        // Long enough for the slowest tick
+        let max_samples_per_tick = (sample_rate * 5) / (MIN_BPM * 2) + 1;
 
         let mut result = Player {
             module,
-            sample_rate: sample_rate,
-            samples_per_tick,
+            sample_rate,
+            samples_per_tick: 0,
+            speed: module.tempo,
+            bpm: module.bpm,
+            global_volume: module.global_volume.min(64),
+            volume: 64,
             pattern_order_index: 0,
             pattern_index: 0,
             row_index: 0,
@@ -60,26 +148,66 @@ impl<'a> Player<'a> {
             loop_count: 0,
             print_rows: false,
             channels: Vec::new(),
-            buffer: vec![0; samples_per_tick * 2],
-            mix_buffer: vec![0; samples_per_tick * 2],
+            tick_remainder: 0,
+            pattern_delay: 0,
+            pattern_loop_rows: vec![0; module.num_channels],
+            pattern_loop_counts: vec![0; module.num_channels],
+            global_volume_slide_params: vec![0; module.num_channels],
+            visited_rows: vec![false; module.pattern_order.len() * MAX_ROWS],
+            buffer: vec![0; max_samples_per_tick * 2],
+            mix_buffer: vec![0; max_samples_per_tick * 2],
             tick_durations: Vec::new(),
             row_cpu_duration: Duration::ZERO,
             row_cpu_usage: 0.0,
         };
 
-        for _ in 0..module.num_channels {
-            result.channels.push(Channel::new(module, sample_rate));
+        for channel_index in 0..module.num_channels {
+            result
+                .channels
+                .push(Channel::new(module, channel_index, sample_rate));
         }
 
         result
     }
 
+    /// Global volume of the song 0-64, as set by its Gxx & Hxy effects
+    pub fn get_global_volume(&self) -> usize {
+        self.global_volume
+    }
+
+    /// Sets global volume of the song 0-64. Following Gxx & Hxy effects change it as
+    /// usual, resetting the player restores the song's initial global volume.
+    pub fn set_global_volume(&mut self, global_volume: usize) {
+        self.global_volume = global_volume.min(64);
+    }
+
+    /// Volume set with `set_volume`, 0-64
+    pub fn get_volume(&self) -> usize {
+        self.volume
+    }
+
+    /// Sets output volume 0-64, e.g. for fading music in & out. It is applied on top of
+    /// the song's global volume, which stays under the song's control. Resetting the
+    /// player keeps it.
+    pub fn set_volume(&mut self, volume: usize) {
+        self.volume = volume.min(64);
+    }
+
     pub fn reset(&mut self) {
         self.pattern_order_index = 0;
         self.pattern_index = 0;
         self.row_index = 0;
         self.row_tick = 0;
         self.loop_count = 0;
+        self.speed = self.module.tempo;
+        self.bpm = self.module.bpm;
+        self.global_volume = self.module.global_volume.min(64);
+        self.tick_remainder = 0;
+        self.pattern_delay = 0;
+        self.pattern_loop_rows.fill(0);
+        self.pattern_loop_counts.fill(0);
+        self.global_volume_slide_params.fill(0);
+        self.visited_rows.fill(false);
         self.buffer.fill(0);
         self.mix_buffer.fill(0);
 
@@ -92,8 +220,9 @@ impl<'a> Player<'a> {
         let mut s = String::new();
 
         for i in 0..self.channels.len() {
-            let pattern_index = self.module.pattern_order[self.pattern_order_index];
-            let row = self.module.patterns[pattern_index].channels[i][self.row_index];
+            let row =
+                self.module
+                    .get_channel_row_ordered(self.pattern_order_index, i, self.row_index);
 
             if self.row_index == 0 {
                 s += "\x1b[0m-+-";
@@ -132,7 +261,7 @@ impl<'a> Player<'a> {
             return Duration::ZERO;
         }
 
-        let num_items = usize::min(self.tick_durations.len(), self.module.tempo);
+        let num_items = usize::min(self.tick_durations.len(), self.speed);
         let slice = &self.tick_durations[self.tick_durations.len() - num_items..];
 
         let mut result = Duration::ZERO;
@@ -148,7 +277,11 @@ impl<'a> Player<'a> {
         self.row_cpu_duration = self.get_last_row_cpu_duration();
         self.tick_durations.clear();
 
-        let mut pattern_break = false;
+        self.row_tick = 0;
+
+        let mut position_jump = None;
+        let mut pattern_break = None;
+        let mut pattern_loop = None;
 
         for channel_index in 0..self.channels.len() {
             let row = self.module.get_channel_row_ordered(
@@ -156,48 +289,193 @@ impl<'a> Player<'a> {
                 channel_index,
                 self.row_index,
             );
-
-            // Pattern break
-            if row.effect_type == 0x0D {
-                self.pattern_order_index += 1;
-                if self.pattern_order_index >= self.module.pattern_order.len() {
-                    self.pattern_order_index = self.module.restart_position;
-                    self.loop_count += 1;
+            let param = row.effect_param;
+
+            match row.effect_type {
+                // Position jump
+                0x0B => position_jump = Some(param as usize),
+                // Pattern break, row is in decimal
+                0x0D => {
+                    pattern_break = Some(((param >> 4) * 10 + param.bitand(0x0F)) as usize);
+                }
+                // Pattern loop, E60 marks loop start
+                0x0E if param.test_high_nibble(0x60) => {
+                    let loop_row = &mut self.pattern_loop_rows[channel_index];
+                    let loop_count = &mut self.pattern_loop_counts[channel_index];
+
+                    if param & 0x0F == 0 {
+                        *loop_row = self.row_index;
+                    } else if *loop_count == 0 {
+                        *loop_count = (param & 0x0F) as usize;
+                        pattern_loop = Some(*loop_row);
+                    } else {
+                        *loop_count -= 1;
+                        if *loop_count > 0 {
+                            pattern_loop = Some(*loop_row);
+                        }
+                    }
                 }
+                _ => {}
+            }
+        }
+
+        // Looped rows are played again without counting as song loop
+        if let Some(loop_row) = pattern_loop {
+            for row_index in loop_row..=self.row_index {
+                self.set_row_visited(self.pattern_order_index, row_index, false);
+            }
+
+            self.row_index = loop_row;
+        }
+        // Position jump and pattern break on the same row combine into jump to given row
+        else if position_jump.is_some() || pattern_break.is_some() {
+            match position_jump {
+                Some(order_index) => self.set_pattern_order(order_index),
+                None => self.set_pattern_order(self.pattern_order_index + 1),
+            }
+
+            self.row_index = pattern_break.unwrap_or(0);
+            if self.row_index >= self.get_num_rows() {
+                self.row_index = 0;
+            }
+        } else {
+            self.row_index += 1;
+
+            if self.row_index >= self.get_num_rows() {
+                self.row_index = 0;
+                self.set_pattern_order(self.pattern_order_index + 1);
+            }
+        }
+
+        // Coming back to a row already played means the song looped
+        if self.is_row_visited(self.pattern_order_index, self.row_index) {
+            self.loop_count += 1;
+            self.visited_rows.fill(false);
+        }
+    }
 
-                self.pattern_index = self.module.pattern_order[self.pattern_order_index];
-                self.row_index =
-                    ((row.effect_param >> 4) * 10 + row.effect_param.bitand(0x0F)) as usize;
+    // Reads row settings, that apply before any channel plays the row
+    fn start_row(&mut self) {
+        self.set_row_visited(self.pattern_order_index, self.row_index, true);
 
-                if self.row_index < self.module.patterns[self.pattern_index].num_rows {
-                    pattern_break = true;
+        self.pattern_delay = 0;
+
+        for channel_index in 0..self.channels.len() {
+            let row = self.module.get_channel_row_ordered(
+                self.pattern_order_index,
+                channel_index,
+                self.row_index,
+            );
+
+            match row.effect_type {
+                // Pattern delay
+                0x0E if row.effect_param.test_high_nibble(0xE0) => {
+                    self.pattern_delay = (row.effect_param & 0x0F) as usize;
                 }
+                // Set speed below 32, BPM from 32 up. Zero is ignored.
+                0x0F => match row.effect_param as usize {
+                    0 => {}
+                    speed @ 1..=31 => self.speed = speed,
+                    bpm => self.bpm = bpm,
+                },
+                // Set speed past 31, only loaded from S3M & IT
+                0x1C if row.effect_param > 0 => self.speed = row.effect_param as usize,
+                _ => {}
             }
         }
+    }
 
-        self.row_tick = 0;
+    // Global volume is shared by all channels, so Gxx & Hxy are applied before any channel
+    // uses it on this tick
+    fn apply_global_volume_effects(&mut self) {
+        for channel_index in 0..self.channels.len() {
+            let row = self.module.get_channel_row_ordered(
+                self.pattern_order_index,
+                channel_index,
+                self.row_index,
+            );
+            let param = row.effect_param;
 
-        if pattern_break {
-            return;
+            match row.effect_type {
+                // Set global volume
+                0x10 if self.row_tick == 0 => {
+                    self.global_volume = (param as usize).min(64);
+                }
+                // Global volume slide, zero parameter continues with the last one
+                0x11 => {
+                    let slide_param = &mut self.global_volume_slide_params[channel_index];
+                    if param != 0 {
+                        *slide_param = param;
+                    }
+
+                    if self.row_tick > 0 {
+                        let up = (*slide_param >> 4) as usize;
+                        let down = (*slide_param & 0x0F) as usize;
+
+                        self.global_volume = if up > 0 {
+                            (self.global_volume + up).min(64)
+                        } else {
+                            self.global_volume.saturating_sub(down)
+                        };
+                    }
+                }
+                _ => {}
+            }
         }
+    }
 
-        self.pattern_index = self.module.pattern_order[self.pattern_order_index];
-        self.row_index += 1;
+    // Tick lasts 2.5 / BPM seconds. Fraction of a sample left over is carried to next
+    // tick, so the song doesn't drift.
+    fn update_samples_per_tick(&mut self) {
+        // BPM is validated when loading, but module could have been built by hand
+        let divisor = self.bpm.max(MIN_BPM) * 2;
+        let dividend = self.sample_rate * 5 + self.tick_remainder;
 
-        if self.row_index >= self.module.patterns[self.pattern_index].num_rows {
-            self.row_index = 0;
-            self.pattern_order_index += 1;
+        self.samples_per_tick = dividend / divisor;
+        self.tick_remainder = dividend % divisor;
+    }
 
-            if self.pattern_order_index >= self.module.pattern_order.len() {
-                self.pattern_order_index = self.module.restart_position;
-                self.row_index = 0;
-                self.loop_count += 1;
-            }
+    // Moves to given pattern in order, past the end of song wraps around to restart position
+    fn set_pattern_order(&mut self, pattern_order_index: usize) {
+        self.pattern_order_index = pattern_order_index;
+
+        if self.pattern_order_index >= self.module.pattern_order.len() {
+            self.pattern_order_index =
+                if self.module.restart_position < self.module.pattern_order.len() {
+                    self.module.restart_position
+                } else {
+                    0
+                };
         }
     }
 
+    fn is_row_visited(&self, pattern_order_index: usize, row_index: usize) -> bool {
+        self.visited_rows
+            .get(pattern_order_index * MAX_ROWS + row_index)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    fn set_row_visited(&mut self, pattern_order_index: usize, row_index: usize, value: bool) {
+        if let Some(visited) = self
+            .visited_rows
+            .get_mut(pattern_order_index * MAX_ROWS + row_index)
+        {
+            *visited = value;
+        }
+    }
+
+    // Number of rows in currently playing pattern
+    fn get_num_rows(&self) -> usize {
+        self.module
+            .get_pattern_ordered(self.pattern_order_index)
+            .map_or(0, |pattern| pattern.num_rows)
+    }
+
     fn tick(&mut self) {
         if self.row_tick == 0 {
+            self.start_row();
+
             if self.print_rows {
                 self.print_row();
             }
@@ -205,57 +483,64 @@ impl<'a> Player<'a> {
 
         let time_start = Instant::now();
 
+        self.update_samples_per_tick();
+        let tick_length = self.samples_per_tick * 2;
+
         // Clear 32bit mix buffer
-        self.mix_buffer.fill(0);
+        self.mix_buffer[..tick_length].fill(0);
 
         let mut channels_tick_duration = Duration::ZERO;
 
+        self.apply_global_volume_effects();
+
+        if let Some(&pattern_index) = self.module.pattern_order.get(self.pattern_order_index) {
+            self.pattern_index = pattern_index;
+        }
+
         for i in 0..self.channels.len() {
             let channel = &mut self.channels[i];
 
-            self.pattern_index = self.module.pattern_order[self.pattern_order_index];
-            let row = self.module.patterns[self.pattern_index].channels[i][self.row_index];
+            let row =
+                self.module
+                    .get_channel_row_ordered(self.pattern_order_index, i, self.row_index);
 
             let channel_tick_start = Instant::now();
-            channel.tick(row, self.row_tick, &mut self.buffer);
+            let buffer = &mut self.buffer[..tick_length];
+            channel.tick(row, self.row_tick, self.speed, self.global_volume, buffer);
             channels_tick_duration += channel_tick_start.elapsed();
 
-            unsafe {
-                let steps = if self.buffer.len() >= 16 {
-                    (self.buffer.len() - 15) / 16
-                } else {
-                    0
-                };
-
-                let mut src = self.buffer.as_ptr() as *const core::arch::x86_64::__m256i;
-                let mut dst = self.mix_buffer.as_mut_ptr() as *mut core::arch::x86_64::__m256i;
-
-                for _ in 0..steps {
-                    *dst = _mm256_adds_epi16(*src, *dst);
-
-                    src = src.add(1);
-                    dst = dst.add(1);
-                }
+            mix_saturating(
+                &mut self.mix_buffer[..tick_length],
+                &self.buffer[..tick_length],
+            );
+        }
 
-                for i in (steps * 16)..self.buffer.len() {
-                    let dst = self.mix_buffer.get_unchecked_mut(i);
-                    *dst = dst.saturating_add(*self.buffer.get_unchecked(i));
-                }
+        if self.volume < 64 {
+            for value in &mut self.mix_buffer[..tick_length] {
+                *value = ((*value as i32 * self.volume as i32) / 64) as i16;
             }
         }
 
         self.tick_durations.push(time_start.elapsed());
         //self.tick_durations.push(channels_tick_duration);
 
-        self.num_generated_samples = self.mix_buffer.len();
+        self.num_generated_samples = tick_length;
 
+        // Pattern delay repeats the row without triggering notes again
         self.row_tick += 1;
-        if self.row_tick == self.module.tempo {
+        if self.row_tick >= self.speed * (1 + self.pattern_delay) {
             self.step_row();
         }
     }
 
+    /// Fills `output` with interleaved 16-bit stereo samples and returns how many were
+    /// written. Zero sample rate writes nothing.
     pub fn render(&mut self, output: &mut [i16]) -> usize {
+        // Ticks would never have any samples
+        if self.sample_rate == 0 {
+            return 0;
+        }
+
         let mut num_filled_samples = 0;
 
         while num_filled_samples < output.len() {
@@ -265,7 +550,8 @@ impl<'a> Player<'a> {
                     output.len() - num_filled_samples,
                 );
 
-                let src = &self.mix_buffer[self.mix_buffer.len() - self.num_generated_samples..];
+                let tick_length = self.samples_per_tick * 2;
+                let src = &self.mix_buffer[tick_length - self.num_generated_samples..tick_length];
                 output[num_filled_samples..num_filled_samples + to_copy]
                     .copy_from_slice(&src[0..to_copy]);
 
@@ -285,8 +571,7 @@ impl<'a> Player<'a> {
         self.print_rows = false;
         self.loop_count = 0;
 
-        let mut buffer = Vec::<i16>::with_capacity(self.sample_rate * 2);
-        buffer.resize(buffer.capacity(), 0);
+        let mut buffer = vec![0; self.sample_rate * 2];
 
         while self.loop_count == 0 {
             self.render(&mut buffer);
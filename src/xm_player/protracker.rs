@@ -0,0 +1,308 @@
+use std::rc::Rc;
+
+use super::module::single_sample_instrument;
+use super::BinaryReader;
+use super::EndOfData;
+use super::LoadError;
+use super::LoopType;
+use super::Module;
+use super::Pattern;
+use super::Row;
+use super::Sample;
+
+// Offset of format signature in 31 sample modules
+const SIGNATURE_POS: usize = 1080;
+
+// Period of C-4 (C-2 in ProTracker octave numbering) with zero finetune
+const MIDDLE_C_PERIOD: f32 = 428.0;
+
+// Same note in XM numbering
+const MIDDLE_C_NOTE: f32 = 49.0;
+
+struct ModFormat {
+    tracker: &'static str,
+    num_samples: usize,
+    num_channels: usize,
+}
+
+fn get_signature_format(signature: &[u8]) -> Option<ModFormat> {
+    let (tracker, num_channels) = match signature {
+        b"M.K." | b"M!K!" => ("ProTracker", 4),
+        b"FLT4" => ("StarTrekker", 4),
+        b"4CHN" => ("FastTracker", 4),
+        // xCHN, e.g. 6CHN and 8CHN
+        [digit @ b'1'..=b'9', b'C', b'H', b'N'] => ("FastTracker", (digit - b'0') as usize),
+        // xxCH, e.g. 10CH up to 32CH
+        [tens @ b'1'..=b'9', ones @ b'0'..=b'9', b'C', b'H'] => {
+            ("FastTracker", ((tens - b'0') * 10 + (ones - b'0')) as usize)
+        }
+        _ => return None,
+    };
+
+    Some(ModFormat {
+        tracker,
+        num_samples: 31,
+        num_channels,
+    })
+}
+
+// Original Soundtracker modules have no signature, only 15 samples and 4 channels.
+// Check that header values are sane, so random data is not taken for a module.
+fn is_soundtracker_module(data: &[u8]) -> bool {
+    // Title, 15 sample headers, song length, restart and pattern order
+    if data.len() < 600 {
+        return false;
+    }
+
+    for i in 0..15 {
+        let header = &data[20 + i * 30..50 + i * 30];
+
+        // Finetune did not exist yet, volume is at most 64
+        if header[24] != 0 || header[25] > 64 {
+            return false;
+        }
+    }
+
+    let song_length = data[470] as usize;
+    let pattern_order = &data[472..600];
+
+    (1..=128).contains(&song_length) && pattern_order.iter().all(|&pattern| pattern < 64)
+}
+
+// Converts Amiga period from pattern data to XM note number
+fn get_period_note(period: u16) -> u8 {
+    if period == 0 {
+        return Row::default().note;
+    }
+
+    let note = MIDDLE_C_NOTE + 12.0 * (MIDDLE_C_PERIOD / (period as f32)).log2();
+    note.round().clamp(1.0, 96.0) as u8
+}
+
+impl Module {
+    pub(super) fn parse_mod(data: &[u8], lenient: bool) -> Result<Module, LoadError> {
+        let format = match data.get(SIGNATURE_POS..SIGNATURE_POS + 4) {
+            Some(signature) => get_signature_format(signature),
+            None => None,
+        };
+
+        let format = match format {
+            Some(format) => format,
+            None if is_soundtracker_module(data) => ModFormat {
+                tracker: "Soundtracker",
+                num_samples: 15,
+                num_channels: 4,
+            },
+            None => return Err(LoadError::BadMagic { offset: 0 }),
+        };
+
+        let mut br = BinaryReader::new(data);
+        let mut result = Module {
+            tracker: format.tracker.to_string(),
+            num_instruments: format.num_samples,
+            num_channels: format.num_channels,
+            // Amiga periods, so pitch effects behave like on ProTracker
+            linear_freq_table: false,
+            tempo: 6,
+            bpm: 125,
+            ..Module::default()
+        };
+
+        // Amiga channels are hard panned left, right, right, left
+        result.channel_panning = (0..format.num_channels)
+            .map(|i| if matches!(i % 4, 0 | 3) { 0 } else { 255 })
+            .collect();
+
+        let truncated = |e: EndOfData| LoadError::TruncatedHeader { offset: e.offset };
+
+        result.name = br
+            .read_string_segment(20)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
+
+        let mut samples = Vec::with_capacity(format.num_samples);
+        for i in 0..format.num_samples {
+            samples.push(parse_sample_header(&mut br, i, format.num_samples == 15)?);
+        }
+
+        let song_length_pos = br.pos;
+        let song_length = br.read_u8().map_err(truncated)? as usize;
+        if song_length == 0 || song_length > 128 {
+            return Err(LoadError::InvalidHeader {
+                offset: song_length_pos,
+                field: "song length",
+                value: song_length,
+            });
+        }
+
+        // Restart position, only meaningful in some trackers (ProTracker stores 127 there)
+        result.restart_position = br.read_u8().map_err(truncated)? as usize;
+        if result.restart_position >= song_length {
+            result.restart_position = 0;
+        }
+
+        // All 128 entries count when looking for number of patterns, even past song length
+        let mut num_patterns = 0;
+        for i in 0..128 {
+            let pattern_index = br.read_u8().map_err(truncated)? as usize;
+            if i < song_length {
+                result.pattern_order.push(pattern_index);
+            }
+
+            num_patterns = num_patterns.max(pattern_index + 1);
+        }
+
+        if format.num_samples == 31 {
+            // Signature
+            br.pos += 4;
+        }
+
+        // Every pattern has 64 rows, patterns cut off by truncated data stay empty
+        result.patterns.resize(num_patterns, Pattern::default());
+        for pattern in &mut result.patterns {
+            pattern.channels.resize(format.num_channels, Vec::new());
+            pattern.resize(64);
+        }
+
+        if let Err(err) = result.parse_mod_patterns(&mut br) {
+            result.recover(err, lenient)?;
+        }
+
+        // Every sample becomes an instrument with just that single sample
+        let mut data_result = Ok(());
+        for (i, mut sample) in samples.into_iter().enumerate() {
+            // Once sample data gets truncated, remaining samples are left empty
+            if data_result.is_ok() {
+                data_result = read_sample_data(&mut sample, &mut br, i);
+            } else {
+                sample.set_length(0);
+            }
+
+            let instrument = single_sample_instrument(Rc::new(sample));
+            result.instruments.push(Rc::new(instrument));
+        }
+
+        if let Err(err) = data_result {
+            result.recover(err, lenient)?;
+        }
+
+        Ok(result)
+    }
+
+    fn parse_mod_patterns(&mut self, br: &mut BinaryReader) -> Result<(), LoadError> {
+        for pattern_index in 0..self.patterns.len() {
+            let pattern = &mut self.patterns[pattern_index];
+
+            let truncated = |e: EndOfData| LoadError::TruncatedPattern {
+                offset: e.offset,
+                pattern: pattern_index,
+            };
+
+            for line in 0..64 {
+                for channel in &mut pattern.channels {
+                    let b0 = br.read_u8().map_err(truncated)?;
+                    let b1 = br.read_u8().map_err(truncated)?;
+                    let b2 = br.read_u8().map_err(truncated)?;
+                    let b3 = br.read_u8().map_err(truncated)?;
+
+                    // Sample number is split between upper nibbles of first and third byte,
+                    // period takes lower 12 bits of first two bytes
+                    let row = &mut channel[line];
+                    row.note = get_period_note(u16::from(b0 & 0x0F) << 8 | u16::from(b1));
+                    row.instrument = (b0 & 0xF0) | (b2 >> 4);
+
+                    // Effect numbers are the same as in XM
+                    row.effect_type = b2 & 0x0F;
+                    row.effect_param = b3;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_sample_header(
+    br: &mut BinaryReader,
+    sample_index: usize,
+    soundtracker: bool,
+) -> Result<Sample, LoadError> {
+    let truncated = |e: EndOfData| LoadError::TruncatedSample {
+        offset: e.offset,
+        instrument: None,
+        sample: sample_index,
+    };
+
+    let mut result = Sample::default();
+
+    result.name = br
+        .read_string_segment(22)
+        .map_err(truncated)?
+        .trim()
+        .to_string();
+
+    // Lengths are stored in 16-bit words
+    let sample_length = br.read_u16_be().map_err(truncated)? as usize * 2;
+
+    // Signed nibble in 1/8 of semitone, XM uses 1/128
+    let finetune = (br.read_u8().map_err(truncated)? & 0x0F) as i8;
+    result.finetune = ((finetune << 4) >> 4) * 16;
+
+    result.volume = br.read_u8().map_err(truncated)?.min(64);
+
+    // Soundtracker stores loop start in bytes, not words
+    let mut loop_start = br.read_u16_be().map_err(truncated)? as usize;
+    if !soundtracker {
+        loop_start *= 2;
+    }
+
+    let loop_length = br.read_u16_be().map_err(truncated)? as usize * 2;
+
+    // One word long loop means no loop at all
+    if loop_length > 2 {
+        result.loop_type = LoopType::Forward;
+        result.loop_start = loop_start as f32;
+        result.loop_end = (loop_start + loop_length) as f32;
+    }
+
+    // Amiga has no sample panning, channels are panned instead
+    result.has_panning = false;
+
+    result.set_length(sample_length);
+    Ok(result)
+}
+
+// MOD sample data is plain signed 8-bit PCM
+fn read_sample_data(
+    sample: &mut Sample,
+    br: &mut BinaryReader,
+    sample_index: usize,
+) -> Result<(), LoadError> {
+    let sample_length = sample.sample_end as usize;
+
+    sample.data.clear();
+    sample
+        .data
+        .reserve(sample_length.min(br.data.len().saturating_sub(br.pos)));
+
+    while sample.data.len() < sample_length {
+        match br.read_i8() {
+            Ok(value) => sample.data.push((value as i16) * 16),
+            Err(_) => break,
+        }
+    }
+
+    if sample.data.len() < sample_length {
+        let offset = br.pos;
+        sample.set_length(sample.data.len());
+
+        return Err(LoadError::TruncatedSample {
+            offset,
+            instrument: None,
+            sample: sample_index,
+        });
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
-use std::error;
-
 use super::BinaryReader;
-use super::FormatError;
+use super::BinaryWriter;
+use super::EndOfData;
+use super::LoadError;
 
 #[derive(Clone, Default)]
 pub enum LoopType {
@@ -21,25 +21,46 @@ pub struct Sample {
     pub sample_end: f32,
     pub volume: u8,
     pub panning: u8,
+    // Note-on resets channel panning to `panning`, otherwise channel keeps its own
+    pub has_panning: bool,
     pub relative_note: i8,
     pub finetune: i8,
+    pub is_16bit: bool,
+    adpcm: bool,
+
+    // Impulse Tracker only, sustain loop is played until note is released
+    pub sustain_loop_type: LoopType,
+    pub sustain_loop_start: f32,
+    pub sustain_loop_end: f32,
 }
 
 impl Sample {
-    pub fn new(br: &mut BinaryReader, data_pos: usize) -> Result<Sample, Box<dyn error::Error>> {
+    // Parses 40 byte sample header, sample data is read separately by `read_data`
+    pub(crate) fn new(
+        br: &mut BinaryReader,
+        instrument_index: usize,
+        sample_index: usize,
+    ) -> Result<Sample, LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedSample {
+            offset: e.offset,
+            instrument: Some(instrument_index),
+            sample: sample_index,
+        };
+
         let mut result = Sample::default();
 
-        let mut sample_length = br.read_u32() as usize;
+        let mut sample_length = br.read_u32().map_err(truncated)? as usize;
 
-        result.loop_start = br.read_u32() as f32;
-        result.loop_end = result.loop_start + (br.read_u32() as f32);
-        result.volume = br.read_u8();
-        result.finetune = br.read_u8() as i8;
+        result.loop_start = br.read_u32().map_err(truncated)? as f32;
+        result.loop_end = result.loop_start + (br.read_u32().map_err(truncated)? as f32);
+        result.volume = br.read_u8().map_err(truncated)?;
+        result.finetune = br.read_i8().map_err(truncated)?;
 
-        let flags = br.read_u8();
+        let flags_pos = br.pos;
+        let flags = br.read_u8().map_err(truncated)?;
 
-        let is_16bit = (flags & 0b10000) != 0;
-        if is_16bit {
+        result.is_16bit = (flags & 0b10000) != 0;
+        if result.is_16bit {
             sample_length >>= 1;
             result.loop_start = (result.loop_start / 2.0).floor();
             result.loop_end = (result.loop_end / 2.0).floor();
@@ -49,50 +70,202 @@ impl Sample {
             0 => Ok(LoopType::None),
             1 => Ok(LoopType::Forward),
             2 => Ok(LoopType::PingPong),
-            _ => Err(Box::new(FormatError::new("Invalid sample loop type"))),
+            loop_type => Err(LoadError::InvalidLoopType {
+                offset: flags_pos,
+                instrument: Some(instrument_index),
+                sample: sample_index,
+                loop_type,
+            }),
         }?;
 
-        result.data.resize(sample_length, 0);
-        result.sample_end = sample_length as f32;
+        // Sloppy loop points must not reach outside of sample data
+        result.set_length(sample_length);
 
-        result.panning = br.read_u8();
-        result.relative_note = br.read_u8() as i8;
+        result.panning = br.read_u8().map_err(truncated)?;
+        result.has_panning = true;
+        result.relative_note = br.read_i8().map_err(truncated)?;
 
-        let compression_type = br.read_u8();
+        let compression_pos = br.pos;
+        let compression_type = br.read_u8().map_err(truncated)?;
 
-        result.name = br.read_string_segment(22).trim().to_string();
+        result.name = br
+            .read_string_segment(22)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
 
-        br.pos = data_pos;
-        result.read_samples(br, compression_type == 0xAD, is_16bit)?;
+        // ModPlug ADPCM compression, only defined for 8-bit samples
+        result.adpcm = compression_type == 0xAD;
+        if result.adpcm && result.is_16bit {
+            return Err(LoadError::UnsupportedCompression {
+                offset: compression_pos,
+                instrument: Some(instrument_index),
+                sample: sample_index,
+                compression: compression_type,
+            });
+        }
 
         Ok(result)
     }
 
-    pub fn read_samples(
+    // Reads delta encoded (or ADPCM compressed) sample data. When data is truncated,
+    // sample is shortened to what was actually read, so it stays playable.
+    pub(crate) fn read_data(
         &mut self,
         br: &mut BinaryReader,
-        adpcm: bool,
-        is_16bit: bool,
-    ) -> Result<(), Box<dyn error::Error>> {
-        if !adpcm {
-            if is_16bit {
-                let mut acc: i16 = 0;
-                for i in 0..self.data.len() {
-                    (acc, _) = acc.overflowing_add(br.read_i16());
-                    self.data[i] = acc;
-                }
-            } else {
-                let mut acc: i8 = 0;
-                for i in 0..self.data.len() {
-                    (acc, _) = acc.overflowing_add(br.read_i8());
-                    self.data[i] = (acc as i16) * 16;
+        instrument_index: usize,
+        sample_index: usize,
+    ) -> Result<(), LoadError> {
+        let sample_length = self.sample_end as usize;
+        let available_bytes = br.data.len().saturating_sub(br.pos);
+        let available = if self.adpcm {
+            available_bytes.saturating_sub(16) * 2
+        } else if self.is_16bit {
+            available_bytes / 2
+        } else {
+            available_bytes
+        };
+
+        self.data.clear();
+        self.data.reserve(sample_length.min(available));
+
+        if self.adpcm {
+            self.read_adpcm_data(br, sample_length);
+        } else if self.is_16bit {
+            let mut acc: i16 = 0;
+            while self.data.len() < sample_length {
+                match br.read_i16() {
+                    Ok(delta) => (acc, _) = acc.overflowing_add(delta),
+                    Err(_) => break,
                 }
+
+                self.data.push(acc);
             }
         } else {
-            // ADPCM compression
-            return Err(Box::new(FormatError::new("ADPCM not supported")));
+            let mut acc: i8 = 0;
+            while self.data.len() < sample_length {
+                match br.read_i8() {
+                    Ok(delta) => (acc, _) = acc.overflowing_add(delta),
+                    Err(_) => break,
+                }
+
+                self.data.push((acc as i16) * 16);
+            }
+        }
+
+        if self.data.len() < sample_length {
+            let offset = br.pos;
+            self.set_length(self.data.len());
+
+            return Err(LoadError::TruncatedSample {
+                offset,
+                instrument: Some(instrument_index),
+                sample: sample_index,
+            });
         }
 
         Ok(())
     }
+
+    // ModPlug ADPCM: 16 byte table of signed 8-bit deltas followed by 4-bit indices
+    // into that table, two samples per byte, low nibble first
+    fn read_adpcm_data(&mut self, br: &mut BinaryReader, sample_length: usize) {
+        let mut delta_table = [0i8; 16];
+        for delta in &mut delta_table {
+            match br.read_i8() {
+                Ok(value) => *delta = value,
+                // Nothing can be decoded without the whole table, sample stays empty and
+                // is reported as truncated
+                Err(_) => return,
+            }
+        }
+
+        let mut acc: i8 = 0;
+        while self.data.len() < sample_length {
+            let Ok(packed) = br.read_u8() else {
+                break;
+            };
+
+            for index in [packed & 0x0F, packed >> 4] {
+                // Odd sample count leaves last high nibble unused
+                if self.data.len() < sample_length {
+                    (acc, _) = acc.overflowing_add(delta_table[index as usize]);
+                    self.data.push((acc as i16) * 16);
+                }
+            }
+        }
+    }
+
+    // Writes 40 byte XM sample header, lengths are in bytes
+    pub(crate) fn write_header(&self, bw: &mut BinaryWriter) {
+        let bytes_per_sample = if self.is_16bit { 2 } else { 1 };
+        let loop_start = self.loop_start as u32;
+        let loop_end = self.loop_end as u32;
+
+        bw.write_u32(self.data.len() as u32 * bytes_per_sample);
+        bw.write_u32(loop_start * bytes_per_sample);
+        bw.write_u32(loop_end.saturating_sub(loop_start) * bytes_per_sample);
+        bw.write_u8(self.volume);
+        bw.write_i8(self.finetune);
+
+        let loop_type = match self.loop_type {
+            LoopType::None => 0,
+            LoopType::Forward => 1,
+            LoopType::PingPong => 2,
+        };
+        bw.write_u8(loop_type | if self.is_16bit { 0x10 } else { 0 });
+
+        // Samples from formats without sample panning are centered
+        bw.write_u8(if self.has_panning { self.panning } else { 128 });
+        bw.write_i8(self.relative_note);
+
+        // Compression type, data is always written delta encoded
+        bw.write_u8(0);
+
+        bw.write_string_segment(&self.name, 22);
+    }
+
+    // Writes delta encoded sample data, 8-bit samples were scaled up when loaded
+    pub(crate) fn write_data(&self, bw: &mut BinaryWriter) {
+        let mut prev: i16 = 0;
+
+        for &value in &self.data {
+            if self.is_16bit {
+                bw.write_i16(value.wrapping_sub(prev));
+                prev = value;
+            } else {
+                let value = value / 16;
+                bw.write_i8((value.wrapping_sub(prev)) as i8);
+                prev = value;
+            }
+        }
+    }
+
+    // Sets relative note & finetune from playback frequency of middle C (C-4), which is
+    // how formats other than XM store sample tuning
+    pub(crate) fn set_middle_c_frequency(&mut self, frequency: u32) {
+        let semitones = 12.0 * (frequency.max(1) as f32 / 8363.0).log2();
+        let relative_note = semitones.floor().clamp(-96.0, 95.0);
+
+        self.relative_note = relative_note as i8;
+        self.finetune = ((semitones - relative_note) * 128.0).clamp(-128.0, 127.0) as i8;
+    }
+
+    // Sets sample length, keeping loops inside of it
+    pub(crate) fn set_length(&mut self, length: usize) {
+        self.sample_end = length as f32;
+        self.loop_end = self.loop_end.min(self.sample_end);
+        self.loop_start = self.loop_start.min(self.loop_end);
+
+        if self.loop_start >= self.loop_end {
+            self.loop_type = LoopType::None;
+        }
+
+        self.sustain_loop_end = self.sustain_loop_end.min(self.sample_end);
+        self.sustain_loop_start = self.sustain_loop_start.min(self.sustain_loop_end);
+
+        if self.sustain_loop_start >= self.sustain_loop_end {
+            self.sustain_loop_type = LoopType::None;
+        }
+    }
 }
@@ -0,0 +1,453 @@
+use std::rc::Rc;
+
+use super::module::read_header_field;
+use super::module::single_sample_instrument;
+use super::BinaryReader;
+use super::EndOfData;
+use super::LoadError;
+use super::LoopType;
+use super::Module;
+use super::Pattern;
+use super::Row;
+use super::Sample;
+
+// Offset of "SCRM" signature
+pub(super) const SIGNATURE_POS: usize = 44;
+
+// Converts S3M effect (A = 1, B = 2, ...) to XM effect type & parameter
+pub(super) fn convert_effect(effect: u8, param: u8, order_remap: &[usize]) -> (u8, u8) {
+    let x = param >> 4;
+    let y = param & 0x0F;
+
+    if !(1..=26).contains(&effect) {
+        return (0, 0);
+    }
+
+    match effect + b'A' - 1 {
+        // Set speed, XM Fxx takes 0x20 and up as BPM, so higher speeds are stored as
+        // unused Sxx
+        b'A' if param > 0x1F => (0x1C, param),
+        b'A' if param > 0 => (0x0F, param),
+        // Position jump, pattern order changed when skip markers were removed
+        b'B' => (
+            0x0B,
+            order_remap
+                .get(param as usize)
+                .map_or(0, |&i| i.min(u8::MAX as usize) as u8),
+        ),
+        // Pattern break
+        b'C' => (0x0D, param),
+        // Volume slide, with fine variants DFy and DxF
+        b'D' if x == 0x0F && y != 0 => (0x0E, 0xB0 | y),
+        b'D' if y == 0x0F && x != 0 => (0x0E, 0xA0 | x),
+        b'D' => (0x0A, param),
+        // Portamento down, EFx is fine and EEx extra fine
+        b'E' if x == 0x0F => (0x0E, 0x20 | y),
+        b'E' if x == 0x0E => (0x21, 0x20 | y),
+        b'E' => (0x02, param),
+        // Portamento up, same variants
+        b'F' if x == 0x0F => (0x0E, 0x10 | y),
+        b'F' if x == 0x0E => (0x21, 0x10 | y),
+        b'F' => (0x01, param),
+        // Tone portamento
+        b'G' => (0x03, param),
+        // Vibrato
+        b'H' => (0x04, param),
+        // Tremor
+        b'I' => (0x1D, param),
+        // Arpeggio
+        b'J' => (0x00, param),
+        // Vibrato + volume slide
+        b'K' => (0x06, param),
+        // Tone portamento + volume slide
+        b'L' => (0x05, param),
+        // Sample offset
+        b'O' => (0x09, param),
+        // Retrigger + volume slide
+        b'Q' => (0x1B, param),
+        // Tremolo
+        b'R' => (0x07, param),
+        // Specials
+        b'S' => match x {
+            // Glissando control
+            0x1 => (0x0E, 0x30 | y),
+            // Set finetune
+            0x2 => (0x0E, 0x50 | y),
+            // Vibrato waveform
+            0x3 => (0x0E, 0x40 | y),
+            // Tremolo waveform
+            0x4 => (0x0E, 0x70 | y),
+            // Set panning
+            0x8 => (0x08, y * 17),
+            // Pattern loop
+            0xB => (0x0E, 0x60 | y),
+            // Note cut
+            0xC => (0x0E, 0xC0 | y),
+            // Note delay
+            0xD => (0x0E, 0xD0 | y),
+            // Pattern delay
+            0xE => (0x0E, 0xE0 | y),
+            _ => (0, 0),
+        },
+        // Fine vibrato, XM has no such effect, so it's stored as unused Uxy
+        b'U' => (0x1E, param),
+        // Set tempo, tempos below 32 are not valid
+        b'T' if param >= 0x20 => (0x0F, param),
+        // Set global volume
+        b'V' => (0x10, param.min(64)),
+        // Set panning, 0x00-0x80 range
+        b'X' if param <= 0x80 => (0x08, (param as usize * 255 / 0x80) as u8),
+        _ => (0, 0),
+    }
+}
+
+impl Module {
+    pub(super) fn parse_s3m(data: &[u8], lenient: bool) -> Result<Module, LoadError> {
+        let mut br = BinaryReader::new(data);
+        let truncated = |e: EndOfData| LoadError::TruncatedHeader { offset: e.offset };
+
+        let mut result = Module {
+            // Scream Tracker uses Amiga periods
+            linear_freq_table: false,
+            ..Module::default()
+        };
+
+        result.name = br
+            .read_string_segment(28)
+            .map_err(truncated)?
+            .trim()
+            .to_string();
+
+        // 0x1A separator and file type (16 = module)
+        let separator_pos = br.pos;
+        if br.read_u8().map_err(truncated)? != 0x1A || br.read_u8().map_err(truncated)? != 16 {
+            return Err(LoadError::BadMagic {
+                offset: separator_pos,
+            });
+        }
+
+        br.pos += 2;
+
+        let num_orders = read_header_field(&mut br, "song length", 1..=256)?;
+        let num_samples = read_header_field(&mut br, "number of instruments", 0..=255)?;
+        let num_patterns = read_header_field(&mut br, "number of patterns", 0..=256)?;
+
+        // Flags, unused
+        br.read_u16().map_err(truncated)?;
+
+        let tracker_version = br.read_u16().map_err(truncated)?;
+        result.version = tracker_version as i32;
+        result.tracker = match tracker_version >> 12 {
+            1 => "Scream Tracker 3",
+            2 => "Imago Orpheus",
+            3 => "Impulse Tracker",
+            4 => "Schism Tracker",
+            5 => "OpenMPT",
+            _ => "Unknown",
+        }
+        .to_string();
+
+        // 1 = signed samples, 2 = unsigned samples
+        let signed_samples = br.read_u16().map_err(truncated)? == 1;
+
+        // "SCRM" signature, checked when detecting format
+        br.pos += 4;
+
+        result.global_volume = br.read_u8().map_err(truncated)?.min(64) as usize;
+
+        // Zero speed means default
+        result.tempo = match br.read_u8().map_err(truncated)? {
+            0 => 6,
+            speed => speed as usize,
+        };
+        result.bpm = br.read_u8().map_err(truncated)?.max(32) as usize;
+
+        let stereo = (br.read_u8().map_err(truncated)? & 0x80) != 0;
+
+        // Ultra click removal, unused
+        br.read_u8().map_err(truncated)?;
+
+        let has_panning_table = br.read_u8().map_err(truncated)? == 252;
+
+        // Reserved & special pointer
+        br.pos += 10;
+
+        // Only enabled PCM channels (0-15) are kept, mapped to consecutive channel indices
+        let channel_settings_pos = br.pos;
+        let mut channel_map = [usize::MAX; 32];
+        for channel_index in &mut channel_map {
+            let settings = br.read_u8().map_err(truncated)?;
+            if settings < 16 {
+                *channel_index = result.num_channels;
+                result.num_channels += 1;
+
+                // Left channels are 0-7, right channels 8-15
+                result.channel_panning.push(match (stereo, settings < 8) {
+                    (false, _) => 128,
+                    (true, true) => 0x3 * 17,
+                    (true, false) => 0xC * 17,
+                });
+            }
+        }
+
+        if result.num_channels == 0 {
+            return Err(LoadError::InvalidHeader {
+                offset: channel_settings_pos,
+                field: "number of channels",
+                value: 0,
+            });
+        }
+
+        let order_remap = result.read_pattern_order(&mut br, num_orders)?;
+
+        let mut sample_pointers = Vec::with_capacity(num_samples);
+        for _ in 0..num_samples {
+            sample_pointers.push(br.read_u16().map_err(truncated)? as usize * 16);
+        }
+
+        let mut pattern_pointers = Vec::with_capacity(num_patterns);
+        for _ in 0..num_patterns {
+            pattern_pointers.push(br.read_u16().map_err(truncated)? as usize * 16);
+        }
+
+        if has_panning_table {
+            for &channel_index in &channel_map {
+                let panning = br.read_u8().map_err(truncated)?;
+
+                // Bit 5 tells that panning is valid
+                if channel_index != usize::MAX && (panning & 0x20) != 0 {
+                    result.channel_panning[channel_index] = (panning & 0x0F) * 17;
+                }
+            }
+        }
+
+        // Pattern order may point to patterns, which do not exist
+        let num_patterns = result
+            .pattern_order
+            .iter()
+            .fold(num_patterns, |n, &pattern_index| n.max(pattern_index + 1));
+
+        // Patterns are reached through pointers, so truncated pattern does not stop
+        // loading the rest
+        let mut pattern_errors = Vec::new();
+        result.patterns.resize(num_patterns, Pattern::default());
+        for (pattern_index, pattern) in result.patterns.iter_mut().enumerate() {
+            pattern.channels.resize(result.num_channels, Vec::new());
+            pattern.resize(64);
+
+            // Zero pointer means empty pattern
+            let pointer = pattern_pointers.get(pattern_index).copied().unwrap_or(0);
+            if pointer == 0 {
+                continue;
+            }
+
+            br.pos = pointer;
+            if let Err(err) = pattern.parse_s3m(&mut br, pattern_index, &channel_map, &order_remap)
+            {
+                pattern_errors.push(err);
+            }
+        }
+
+        for err in pattern_errors {
+            result.recover(err, lenient)?;
+        }
+
+        result.num_instruments = num_samples;
+        for (i, &pointer) in sample_pointers.iter().enumerate() {
+            br.pos = pointer;
+
+            let sample = match parse_sample(&mut br, i, signed_samples) {
+                Ok(sample) => sample,
+                Err((err, sample)) => {
+                    result.recover(err, lenient)?;
+                    sample
+                }
+            };
+
+            // Every sample becomes an instrument with just that single sample
+            let instrument = single_sample_instrument(Rc::new(sample));
+            result.instruments.push(Rc::new(instrument));
+        }
+
+        Ok(result)
+    }
+}
+
+impl Pattern {
+    fn parse_s3m(
+        &mut self,
+        br: &mut BinaryReader,
+        pattern_index: usize,
+        channel_map: &[usize; 32],
+        order_remap: &[usize],
+    ) -> Result<(), LoadError> {
+        let truncated = |e: EndOfData| LoadError::TruncatedPattern {
+            offset: e.offset,
+            pattern: pattern_index,
+        };
+
+        // Packed data length, includes the length itself
+        br.read_u16().map_err(truncated)?;
+
+        let mut line = 0;
+        while line < 64 {
+            let what = br.read_u8().map_err(truncated)?;
+
+            // End of row
+            if what == 0 {
+                line += 1;
+                continue;
+            }
+
+            let mut row = Row::default();
+
+            if (what & 0x20) != 0 {
+                let note = br.read_u8().map_err(truncated)?;
+                row.instrument = br.read_u8().map_err(truncated)?;
+
+                row.note = match note {
+                    255 => Row::default().note,
+                    // Note cut
+                    254 => 98,
+                    // Upper nibble is octave, lower nibble is note
+                    _ => ((note >> 4) * 12 + (note & 0x0F) + 1).clamp(1, 96),
+                };
+            }
+
+            if (what & 0x40) != 0 {
+                row.volume = 0x10 + br.read_u8().map_err(truncated)?.min(64);
+            }
+
+            if (what & 0x80) != 0 {
+                let effect = br.read_u8().map_err(truncated)?;
+                let param = br.read_u8().map_err(truncated)?;
+                (row.effect_type, row.effect_param) = convert_effect(effect, param, order_remap);
+            }
+
+            let channel_index = channel_map[(what & 0x1F) as usize];
+            if channel_index != usize::MAX {
+                self.channels[channel_index][line] = row;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Returns partially loaded sample together with error, so it can be kept in lenient mode
+fn parse_sample(
+    br: &mut BinaryReader,
+    sample_index: usize,
+    signed_samples: bool,
+) -> Result<Sample, (LoadError, Sample)> {
+    let mut result = Sample::default();
+
+    let truncated = |e: EndOfData| LoadError::TruncatedSample {
+        offset: e.offset,
+        instrument: None,
+        sample: sample_index,
+    };
+
+    macro_rules! read {
+        ($e:expr) => {
+            match $e {
+                Ok(value) => value,
+                Err(e) => return Err((truncated(e), result)),
+            }
+        };
+    }
+
+    // 1 = PCM sample, anything else is empty or AdLib instrument
+    let sample_type = read!(br.read_u8());
+
+    // DOS file name
+    br.pos += 12;
+
+    let data_pointer_high = read!(br.read_u8()) as usize;
+    let data_pointer_low = read!(br.read_u16()) as usize;
+    let data_pos = ((data_pointer_high << 16) | data_pointer_low) * 16;
+
+    let sample_length = read!(br.read_u32()) as usize;
+    let loop_start = read!(br.read_u32());
+    let loop_end = read!(br.read_u32());
+    result.volume = read!(br.read_u8()).min(64);
+
+    // Reserved
+    br.pos += 1;
+
+    let pack_pos = br.pos;
+    let pack = read!(br.read_u8());
+    let flags = read!(br.read_u8());
+
+    result.set_middle_c_frequency(read!(br.read_u32()));
+
+    // Reserved
+    br.pos += 12;
+
+    result.name = read!(br.read_string_segment(28)).trim().to_string();
+
+    if sample_type != 1 {
+        return Ok(result);
+    }
+
+    if pack != 0 {
+        return Err((
+            LoadError::UnsupportedCompression {
+                offset: pack_pos,
+                instrument: None,
+                sample: sample_index,
+                compression: pack,
+            },
+            result,
+        ));
+    }
+
+    if (flags & 1) != 0 {
+        result.loop_type = LoopType::Forward;
+        result.loop_start = loop_start as f32;
+        result.loop_end = loop_end as f32;
+    }
+
+    result.is_16bit = (flags & 4) != 0;
+    result.set_length(sample_length);
+
+    // Stereo samples store left channel first, only that one is used
+    br.pos = data_pos;
+    result
+        .data
+        .reserve(sample_length.min(br.data.len().saturating_sub(br.pos)));
+
+    while result.data.len() < sample_length {
+        let value = if result.is_16bit {
+            match br.read_u16() {
+                Ok(value) if signed_samples => value as i16,
+                Ok(value) => (value ^ 0x8000) as i16,
+                Err(_) => break,
+            }
+        } else {
+            match br.read_u8() {
+                Ok(value) if signed_samples => (value as i8 as i16) * 16,
+                Ok(value) => ((value ^ 0x80) as i8 as i16) * 16,
+                Err(_) => break,
+            }
+        };
+
+        result.data.push(value);
+    }
+
+    if result.data.len() < sample_length {
+        let offset = br.pos;
+        result.set_length(result.data.len());
+
+        return Err((
+            LoadError::TruncatedSample {
+                offset,
+                instrument: None,
+                sample: sample_index,
+            },
+            result,
+        ));
+    }
+
+    Ok(result)
+}
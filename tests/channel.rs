@@ -0,0 +1,761 @@
+use std::rc::Rc;
+
+use xm_player::{Channel, Envelope, Instrument, LoopType, Module, Row, Sample};
+
+const SAMPLE_RATE: usize = 1000;
+const SPEED: usize = 6;
+
+// Period of C-4 (row note 49) on linear frequency table
+const C4_PERIOD: f32 = 4608.0;
+
+fn note(note: u8, instrument: u8) -> Row {
+    Row {
+        note,
+        instrument,
+        ..Row::default()
+    }
+}
+
+fn effect(effect_type: u8, effect_param: u8) -> Row {
+    Row {
+        effect_type,
+        effect_param,
+        ..Row::default()
+    }
+}
+
+fn with_effect(row: Row, effect_type: u8, effect_param: u8) -> Row {
+    Row {
+        effect_type,
+        effect_param,
+        ..row
+    }
+}
+
+fn volume_column(volume: u8) -> Row {
+    Row {
+        volume,
+        ..Row::default()
+    }
+}
+
+// Constant, looping sample at full volume
+fn looping_sample() -> Sample {
+    let mut sample = Sample::default();
+    sample.data = vec![1024; 64];
+    sample.loop_type = LoopType::Forward;
+    sample.loop_end = 64.0;
+    sample.sample_end = 64.0;
+    sample.volume = 64;
+    sample
+}
+
+// Module with a single instrument, which plays `sample` on all notes
+fn make_module(linear_freq_table: bool, instrument: Instrument, sample: Sample) -> Module {
+    Module {
+        instruments: vec![Rc::new(Instrument {
+            samples: vec![Rc::new(sample)],
+            sample_keymap: vec![0; 96],
+            ..instrument
+        })],
+        num_instruments: 1,
+        num_channels: 1,
+        linear_freq_table,
+        tempo: SPEED,
+        bpm: 125,
+        ..Module::default()
+    }
+}
+
+fn default_module() -> Module {
+    make_module(true, Instrument::default(), looping_sample())
+}
+
+// Envelope holding the same value all the time
+fn constant_envelope(value: usize) -> Envelope {
+    let mut envelope = Envelope {
+        points: vec![(0, value), (1, value)],
+        enabled: true,
+        ..Envelope::default()
+    };
+
+    envelope.build();
+    envelope
+}
+
+// Channel state after a tick
+struct Tick {
+    period: f32,
+    volume: usize,
+    panning: usize,
+    // Anything was mixed into the buffer
+    playing: bool,
+}
+
+// Plays all ticks of a row
+fn play_row(channel: &mut Channel, row: Row) -> Vec<Tick> {
+    play_row_at_speed(channel, row, SPEED)
+}
+
+fn play_row_at_speed(channel: &mut Channel, row: Row, speed: usize) -> Vec<Tick> {
+    let mut buffer = vec![0i16; 40];
+
+    (0..speed)
+        .map(|row_tick| {
+            channel.tick(row, row_tick, speed, 64, &mut buffer);
+
+            Tick {
+                period: channel.get_period(),
+                volume: channel.get_volume(),
+                panning: channel.get_panning(),
+                playing: buffer.iter().any(|&value| value != 0),
+            }
+        })
+        .collect()
+}
+
+fn periods(channel: &mut Channel, row: Row) -> Vec<f32> {
+    play_row(channel, row)
+        .iter()
+        .map(|tick| tick.period)
+        .collect()
+}
+
+// Offsets of played periods from C-4
+fn period_offsets(channel: &mut Channel, row: Row) -> Vec<f32> {
+    periods(channel, row)
+        .iter()
+        .map(|period| period - C4_PERIOD)
+        .collect()
+}
+
+fn volumes(channel: &mut Channel, row: Row) -> Vec<usize> {
+    play_row(channel, row)
+        .iter()
+        .map(|tick| tick.volume)
+        .collect()
+}
+
+fn pannings(channel: &mut Channel, row: Row) -> Vec<usize> {
+    play_row(channel, row)
+        .iter()
+        .map(|tick| tick.panning)
+        .collect()
+}
+
+// Module with one instrument, which plays a quiet sample panned left below C-4 and a
+// loud one panned right from C-4 up
+fn multi_sample_module() -> Module {
+    let sample = |volume, panning| {
+        let mut sample = looping_sample();
+        sample.volume = volume;
+        sample.has_panning = true;
+        sample.panning = panning;
+        Rc::new(sample)
+    };
+
+    let mut sample_keymap = vec![0; 48];
+    sample_keymap.resize(96, 1);
+
+    let instrument = Instrument {
+        samples: vec![sample(32, 0x40), sample(64, 0xC0)],
+        sample_keymap,
+        ..Instrument::default()
+    };
+
+    Module {
+        instruments: vec![Rc::new(instrument)],
+        ..default_module()
+    }
+}
+
+#[test]
+fn note_with_instrument_picks_sample_from_keymap() {
+    let module = multi_sample_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+    assert_eq!(volumes(&mut channel, note(37, 1)), [32; SPEED]);
+    assert_eq!(pannings(&mut channel, note(37, 1)), [0x40; SPEED]);
+    assert_eq!(volumes(&mut channel, note(61, 1)), [64; SPEED]);
+    assert_eq!(pannings(&mut channel, note(61, 1)), [0xC0; SPEED]);
+}
+
+#[test]
+fn note_without_instrument_picks_sample_from_keymap() {
+    let module = multi_sample_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(37, 1));
+
+    // Current instrument's keymap decides the sample for every new note
+    assert_eq!(volumes(&mut channel, note(61, 0)), [64; SPEED]);
+    assert_eq!(pannings(&mut channel, note(61, 0)), [0xC0; SPEED]);
+    assert_eq!(volumes(&mut channel, note(37, 0)), [32; SPEED]);
+    assert_eq!(pannings(&mut channel, note(37, 0)), [0x40; SPEED]);
+}
+
+#[test]
+fn instrument_without_note_resets_volume_and_panning() {
+    let module = multi_sample_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(61, 1));
+    play_row(&mut channel, effect(0x0C, 0x10));
+    play_row(&mut channel, effect(0x08, 0x80));
+
+    // Playing sample keeps going with its default volume & panning
+    let instrument = Row {
+        instrument: 1,
+        ..Row::default()
+    };
+    let ticks = play_row(&mut channel, instrument);
+    assert!(ticks
+        .iter()
+        .all(|tick| tick.volume == 64 && tick.panning == 0xC0 && tick.playing));
+}
+
+// Module whose instrument has given panning envelope and sample panning
+fn panning_envelope_module(panning_envelope: Envelope, panning: u8) -> Module {
+    let mut sample = looping_sample();
+    sample.has_panning = true;
+    sample.panning = panning;
+
+    let instrument = Instrument {
+        panning_envelope,
+        ..Instrument::default()
+    };
+
+    make_module(true, instrument, sample)
+}
+
+#[test]
+fn panning_envelope_swings_around_note_panning() {
+    // Envelope value 32 is center, range is limited by distance to the nearest edge
+    for (panning, envelope_value, expected) in [
+        (128, 32, 128),
+        (128, 64, 255),
+        (128, 0, 0),
+        (64, 48, 96),
+        (64, 0, 0),
+        (224, 0, 192),
+        (224, 64, 255),
+    ] {
+        let module = panning_envelope_module(constant_envelope(envelope_value), panning);
+        let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+        assert_eq!(
+            pannings(&mut channel, note(49, 1)),
+            [expected; SPEED],
+            "panning {}, envelope {}",
+            panning,
+            envelope_value
+        );
+    }
+}
+
+#[test]
+fn panning_envelope_follows_ticks() {
+    let mut envelope = Envelope {
+        points: vec![(0, 32), (4, 64)],
+        enabled: true,
+        ..Envelope::default()
+    };
+    envelope.build();
+
+    let module = panning_envelope_module(envelope, 128);
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    assert_eq!(
+        pannings(&mut channel, note(49, 1)),
+        [128, 160, 192, 224, 255, 255]
+    );
+
+    // Without envelope, note panning is used as is
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+    assert_eq!(pannings(&mut channel, effect(0x08, 0x40)), [0x40; SPEED]);
+}
+
+// Module whose instrument has constant full volume envelope and given fadeout
+fn fadeout_module(fadeout: u16) -> Module {
+    let mut volume_envelope = constant_envelope(64);
+    volume_envelope.fadeout = fadeout;
+
+    let instrument = Instrument {
+        volume_envelope,
+        ..Instrument::default()
+    };
+
+    make_module(true, instrument, looping_sample())
+}
+
+#[test]
+fn released_note_fades_out_and_is_killed() {
+    let module = fadeout_module(16384);
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    assert_eq!(volumes(&mut channel, note(49, 1)), [64; SPEED]);
+
+    // Fadeout starts on the key-off tick, voice is freed once it reaches zero
+    let ticks = play_row(&mut channel, note(97, 0));
+    let volumes: Vec<_> = ticks.iter().take(4).map(|tick| tick.volume).collect();
+    assert_eq!(volumes, [48, 32, 16, 0]);
+
+    let playing: Vec<_> = ticks.iter().map(|tick| tick.playing).collect();
+    assert_eq!(playing, [true, true, true, false, false, false]);
+
+    // Killed voice stays silent, even when volume is set again
+    let ticks = play_row(&mut channel, effect(0x0C, 0x40));
+    assert!(ticks.iter().all(|tick| !tick.playing));
+}
+
+#[test]
+fn released_note_without_fadeout_keeps_playing() {
+    let module = fadeout_module(0);
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    let ticks = play_row(&mut channel, note(97, 0));
+    assert!(ticks.iter().all(|tick| tick.volume == 64 && tick.playing));
+}
+
+#[test]
+fn key_off_without_volume_envelope_cuts_note() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    assert_eq!(volumes(&mut channel, note(97, 0)), [0; SPEED]);
+}
+
+#[test]
+fn auto_vibrato_sweeps_in() {
+    // Square wave flipping every tick, full depth of 4 is reached in 4 ticks
+    let instrument = Instrument {
+        vibrato_type: 1,
+        vibrato_rate: 128,
+        vibrato_depth: 4,
+        vibrato_sweep: 4,
+        ..Instrument::default()
+    };
+    let module = make_module(true, instrument, looping_sample());
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+    let offsets = [1.0, -2.0, 3.0, -4.0, 4.0, -4.0];
+    assert_eq!(period_offsets(&mut channel, note(49, 1)), offsets);
+
+    // Full depth continues on the next row, new note sweeps in again
+    assert_eq!(
+        period_offsets(&mut channel, Row::default()),
+        [4.0, -4.0, 4.0, -4.0, 4.0, -4.0]
+    );
+    assert_eq!(period_offsets(&mut channel, note(49, 1)), offsets);
+}
+
+#[test]
+fn auto_vibrato_sweep_stops_on_key_off() {
+    let instrument = Instrument {
+        vibrato_type: 1,
+        vibrato_rate: 128,
+        vibrato_depth: 4,
+        vibrato_sweep: 4,
+        ..Instrument::default()
+    };
+    let module = make_module(true, instrument, looping_sample());
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+    // Key-off half way through the sweep keeps the depth reached
+    play_row_at_speed(&mut channel, note(49, 1), 2);
+    assert_eq!(
+        period_offsets(&mut channel, note(97, 0)),
+        [2.0, -2.0, 2.0, -2.0, 2.0, -2.0]
+    );
+}
+
+#[test]
+fn auto_vibrato_without_sweep_starts_at_full_depth() {
+    let instrument = Instrument {
+        vibrato_type: 1,
+        vibrato_rate: 128,
+        vibrato_depth: 4,
+        ..Instrument::default()
+    };
+    let module = make_module(true, instrument, looping_sample());
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+    assert_eq!(
+        period_offsets(&mut channel, note(49, 1)),
+        [4.0, -4.0, 4.0, -4.0, 4.0, -4.0]
+    );
+}
+
+#[test]
+fn amiga_periods_interpolate_finetune() {
+    // Row note, sample finetune in 1/128 semitones and period
+    for (row_note, finetune, expected) in [
+        (49, 0, 1712.0),
+        (49, 64, 1664.0),
+        (50, -128, 1712.0),
+        // B-4 interpolates towards C-5, which is the last entry of the table
+        (60, 64, 881.5),
+        (61, 0, 856.0),
+        (37, 0, 3424.0),
+        (37, 64, 3328.0),
+    ] {
+        let mut sample = looping_sample();
+        sample.finetune = finetune;
+
+        let module = make_module(false, Instrument::default(), sample);
+        let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+        assert_eq!(
+            periods(&mut channel, note(row_note, 1))[0],
+            expected,
+            "note {}, finetune {}",
+            row_note,
+            finetune
+        );
+    }
+}
+
+#[test]
+fn glissando_only_rounds_tone_portamento() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+
+    // Glissando on, C-4 slides slowly towards C#4
+    play_row(&mut channel, with_effect(note(49, 1), 0x0E, 0x31));
+    assert_eq!(
+        periods(&mut channel, with_effect(note(50, 0), 0x03, 0x01)),
+        [C4_PERIOD; SPEED]
+    );
+
+    // Portamento up continues from where the tone portamento was, without rounding
+    assert_eq!(
+        periods(&mut channel, effect(0x01, 0x02)),
+        [4588.0, 4580.0, 4572.0, 4564.0, 4556.0, 4548.0]
+    );
+
+    // Tone portamento towards the earlier target is rounded again
+    assert_eq!(periods(&mut channel, effect(0x03, 0x00)), [4544.0; SPEED]);
+}
+
+#[test]
+fn portamento_memories() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    // Slides happen on all ticks but the first, zero parameter uses the last one
+    assert_eq!(
+        periods(&mut channel, effect(0x01, 0x04)),
+        [4608.0, 4592.0, 4576.0, 4560.0, 4544.0, 4528.0]
+    );
+    assert_eq!(periods(&mut channel, effect(0x01, 0x00))[5], 4448.0);
+
+    // Every variant has its own memory, which is still empty for portamento down
+    assert_eq!(periods(&mut channel, effect(0x02, 0x00)), [4448.0; SPEED]);
+
+    // Fine and extra fine portamento only slide on the first tick
+    assert_eq!(periods(&mut channel, effect(0x0E, 0x12)), [4440.0; SPEED]);
+    assert_eq!(periods(&mut channel, effect(0x0E, 0x10)), [4432.0; SPEED]);
+    assert_eq!(periods(&mut channel, effect(0x21, 0x13)), [4429.0; SPEED]);
+    assert_eq!(periods(&mut channel, effect(0x21, 0x10)), [4426.0; SPEED]);
+    assert_eq!(periods(&mut channel, effect(0x0E, 0x21)), [4430.0; SPEED]);
+    assert_eq!(periods(&mut channel, effect(0x21, 0x21)), [4431.0; SPEED]);
+
+    assert_eq!(
+        periods(&mut channel, effect(0x02, 0x02)),
+        [4431.0, 4439.0, 4447.0, 4455.0, 4463.0, 4471.0]
+    );
+}
+
+#[test]
+fn portamento_is_clamped() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    assert_eq!(
+        periods(&mut channel, effect(0x01, 0xFF)),
+        [4608.0, 3588.0, 2568.0, 1548.0, 528.0, 1.0]
+    );
+
+    // Lowest note slides down until the highest period
+    play_row(&mut channel, note(1, 1));
+    let mut last_period = 0.0;
+    for _ in 0..6 {
+        last_period = periods(&mut channel, effect(0x02, 0xFF))[SPEED - 1];
+        assert!(last_period <= 31999.0);
+    }
+
+    assert_eq!(last_period, 31999.0);
+}
+
+#[test]
+fn vibrato_follows_sine_table() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    // Position moves by 4 * speed on all ticks but the first
+    assert_eq!(
+        period_offsets(&mut channel, effect(0x04, 0x48)),
+        [0.0, 0.0, 24.0, 45.0, 58.0, 63.0]
+    );
+
+    // First tick keeps the last offset, second half of the wave is negative
+    assert_eq!(
+        period_offsets(&mut channel, effect(0x04, 0x00)),
+        [63.0, 58.0, 45.0, 24.0, 0.0, -24.0]
+    );
+
+    assert_eq!(period_offsets(&mut channel, Row::default()), [0.0; SPEED]);
+}
+
+#[test]
+fn fine_vibrato_is_quarter_of_vibrato() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    // S3M & IT fine vibrato (Uxy) is loaded as effect 0x1E
+    assert_eq!(
+        period_offsets(&mut channel, effect(0x1E, 0x48)),
+        [0.0, 0.0, 6.0, 11.0, 14.0, 15.0]
+    );
+
+    // Offset is kept on the first tick of the next row, like with normal vibrato
+    assert_eq!(period_offsets(&mut channel, effect(0x1E, 0x00))[0], 15.0);
+}
+
+// Volumes of a tremolo row with ramp waveform, which is played after `vibrato` row
+fn ramp_tremolo_volumes(vibrato: Row) -> Vec<usize> {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    let ramp_tremolo = Row {
+        volume: 0x10 + 32,
+        ..with_effect(note(49, 1), 0x0E, 0x71)
+    };
+    play_row(&mut channel, ramp_tremolo);
+    play_row(&mut channel, vibrato);
+
+    volumes(&mut channel, effect(0x07, 0x48))
+}
+
+#[test]
+fn tremolo_ramp_direction_follows_vibrato() {
+    assert_eq!(
+        ramp_tremolo_volumes(Row::default()),
+        [32, 32, 36, 40, 44, 48]
+    );
+
+    // Vibrato position in second half of the wave turns the tremolo ramp down (FT2)
+    assert_eq!(
+        ramp_tremolo_volumes(effect(0x04, 0x71)),
+        [32, 63, 59, 55, 51, 47]
+    );
+}
+
+// Semitones above C-4 played on each tick of arpeggio row
+fn arpeggio_notes(param: u8, speed: usize) -> Vec<f32> {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    play_row_at_speed(&mut channel, effect(0x00, param), speed)
+        .iter()
+        .map(|tick| (C4_PERIOD - tick.period) / 64.0)
+        .collect()
+}
+
+#[test]
+fn arpeggio_order_depends_on_speed() {
+    // Note is picked from the number of ticks left in the row
+    assert_eq!(arpeggio_notes(0x37, 6), [0.0, 7.0, 3.0, 0.0, 7.0, 3.0]);
+    assert_eq!(arpeggio_notes(0x37, 5), [0.0, 3.0, 0.0, 7.0, 3.0]);
+
+    // Past 16 ticks left FT2 reads outside of its table, giving the second note, while
+    // exactly 16 ticks left plays the base note
+    let notes = arpeggio_notes(0x37, 18);
+    assert_eq!(notes[..5], [0.0, 7.0, 0.0, 0.0, 7.0]);
+}
+
+#[test]
+fn arpeggio_ends_with_row() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+    play_row(&mut channel, effect(0x00, 0x37));
+
+    assert_eq!(periods(&mut channel, Row::default()), [C4_PERIOD; SPEED]);
+}
+
+#[test]
+fn volume_slide_memories() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(
+        &mut channel,
+        Row {
+            volume: 0x10 + 32,
+            ..note(49, 1)
+        },
+    );
+
+    // Axy slides on all ticks but the first, zero parameter uses the last one
+    assert_eq!(
+        volumes(&mut channel, effect(0x0A, 0x20)),
+        [32, 34, 36, 38, 40, 42]
+    );
+    assert_eq!(volumes(&mut channel, effect(0x0A, 0x00))[5], 52);
+
+    // Slide up wins when both are given, volume stays in 0-64
+    assert_eq!(volumes(&mut channel, effect(0x0A, 0x23))[5], 62);
+    assert_eq!(
+        volumes(&mut channel, effect(0x0A, 0xF0)),
+        [62, 64, 64, 64, 64, 64]
+    );
+    assert_eq!(
+        volumes(&mut channel, effect(0x0A, 0x08)),
+        [64, 56, 48, 40, 32, 24]
+    );
+
+    // 5xy & 6xy share the memory with Axy
+    assert_eq!(volumes(&mut channel, effect(0x06, 0x00))[5], 0);
+    play_row(
+        &mut channel,
+        Row {
+            volume: 0x10 + 48,
+            ..note(49, 1)
+        },
+    );
+
+    // Fine slides on the first tick only, each direction with its own memory
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xA3)), [51; SPEED]);
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xA0)), [54; SPEED]);
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xB0)), [54; SPEED]);
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xB5)), [49; SPEED]);
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xB0)), [44; SPEED]);
+}
+
+#[test]
+fn volume_column_slides() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(
+        &mut channel,
+        Row {
+            volume: 0x10 + 32,
+            ..note(49, 1)
+        },
+    );
+
+    // Slides have no memory, fine slides happen on the first tick only
+    assert_eq!(
+        volumes(&mut channel, volume_column(0x63)),
+        [32, 29, 26, 23, 20, 17]
+    );
+    assert_eq!(volumes(&mut channel, volume_column(0x60)), [17; SPEED]);
+    assert_eq!(
+        volumes(&mut channel, volume_column(0x72)),
+        [17, 19, 21, 23, 25, 27]
+    );
+    assert_eq!(volumes(&mut channel, volume_column(0x81)), [26; SPEED]);
+    assert_eq!(volumes(&mut channel, volume_column(0x94)), [30; SPEED]);
+    assert_eq!(volumes(&mut channel, volume_column(0x90)), [30; SPEED]);
+}
+
+#[test]
+fn set_volume_and_panning_without_note() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    // Cxx works on any row, values above 64 are clamped
+    assert_eq!(volumes(&mut channel, effect(0x0C, 0x20)), [32; SPEED]);
+    assert_eq!(volumes(&mut channel, effect(0x0C, 0x7F)), [64; SPEED]);
+
+    // Volume column set volume & panning
+    assert_eq!(volumes(&mut channel, volume_column(0x10 + 16)), [16; SPEED]);
+    assert_eq!(volumes(&mut channel, volume_column(0x50)), [64; SPEED]);
+    assert_eq!(pannings(&mut channel, volume_column(0xC4)), [0x40; SPEED]);
+    assert_eq!(pannings(&mut channel, effect(0x08, 0x80)), [0x80; SPEED]);
+
+    // Both on the same row as a note, effect column is applied last
+    let row = Row {
+        volume: 0x10 + 16,
+        ..with_effect(note(49, 1), 0x0C, 0x30)
+    };
+    assert_eq!(volumes(&mut channel, row), [48; SPEED]);
+}
+
+#[test]
+fn volume_column_panning_slides() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, with_effect(note(49, 1), 0x08, 0x80));
+
+    // Lx & Rx slide on all ticks but the first, without memory
+    assert_eq!(
+        pannings(&mut channel, volume_column(0xD4)),
+        [128, 124, 120, 116, 112, 108]
+    );
+    assert_eq!(pannings(&mut channel, volume_column(0xD0)), [108; SPEED]);
+    assert_eq!(
+        pannings(&mut channel, volume_column(0xE8)),
+        [108, 116, 124, 132, 140, 148]
+    );
+
+    // Panning stays in 0-255
+    assert_eq!(pannings(&mut channel, volume_column(0xCF)), [0xF0; SPEED]);
+    assert_eq!(
+        pannings(&mut channel, volume_column(0xEF)),
+        [240, 255, 255, 255, 255, 255]
+    );
+    play_row(&mut channel, effect(0x08, 0x10));
+    assert_eq!(
+        pannings(&mut channel, volume_column(0xDF)),
+        [16, 1, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn note_cut_on_tick() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    assert_eq!(
+        volumes(&mut channel, effect(0x0E, 0xC3)),
+        [64, 64, 64, 0, 0, 0]
+    );
+
+    play_row(&mut channel, note(49, 1));
+    assert_eq!(volumes(&mut channel, effect(0x0E, 0xC0)), [0; SPEED]);
+}
+
+#[test]
+fn note_delay_starts_row_late() {
+    let module = default_module();
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    play_row(&mut channel, note(49, 1));
+
+    // Previous note plays until the delayed one, which also gets its volume column then
+    let row = Row {
+        volume: 0x10 + 16,
+        ..with_effect(note(61, 1), 0x0E, 0xD2)
+    };
+    let ticks = play_row(&mut channel, row);
+
+    let periods: Vec<_> = ticks.iter().map(|tick| tick.period).collect();
+    assert_eq!(
+        periods,
+        [C4_PERIOD, C4_PERIOD, 3840.0, 3840.0, 3840.0, 3840.0]
+    );
+
+    let volumes: Vec<_> = ticks.iter().map(|tick| tick.volume).collect();
+    assert_eq!(volumes, [64, 64, 16, 16, 16, 16]);
+
+    // Delay past the end of the row never plays the note
+    let mut channel = Channel::new(&module, 0, SAMPLE_RATE);
+    let ticks = play_row(&mut channel, with_effect(note(49, 1), 0x0E, 0xD6));
+    assert!(ticks.iter().all(|tick| !tick.playing));
+}
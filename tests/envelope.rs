@@ -0,0 +1,164 @@
+use xm_player::Envelope;
+
+// Envelope rising by one per tick, points at ticks 0, 2, 4, 6, 8
+fn make_envelope(sustain: Option<usize>, envelope_loop: Option<(usize, usize)>) -> Envelope {
+    let mut envelope = Envelope {
+        points: (0..5).map(|i| (i * 2, i * 2)).collect(),
+        enabled: true,
+        sustain_enabled: sustain.is_some(),
+        loop_enabled: envelope_loop.is_some(),
+        sustain_point: sustain.unwrap_or(0),
+        loop_start_point: envelope_loop.map_or(0, |l| l.0),
+        loop_end_point: envelope_loop.map_or(0, |l| l.1),
+        ..Envelope::default()
+    };
+
+    envelope.build();
+    envelope
+}
+
+// Envelope values of the first `num_ticks` ticks of a note, key-off on tick `release_tick`
+fn play(envelope: &Envelope, num_ticks: usize, release_tick: Option<usize>) -> Vec<u8> {
+    let mut ticks = Envelope::START_TICK;
+    let mut released = false;
+    let mut values = Vec::new();
+
+    for tick in 0..num_ticks {
+        if release_tick == Some(tick) {
+            ticks = envelope.release(ticks);
+            released = true;
+        }
+
+        ticks = envelope.follow(ticks, released);
+        values.push(envelope.get_value(ticks));
+    }
+
+    values
+}
+
+#[test]
+fn no_sustain_no_loop() {
+    let envelope = make_envelope(None, None);
+
+    assert_eq!(play(&envelope, 11, None), [0, 1, 2, 3, 4, 5, 6, 7, 8, 8, 8]);
+}
+
+#[test]
+fn disabled_envelope_is_not_built() {
+    let mut envelope = make_envelope(Some(1), Some((1, 3)));
+    envelope.enabled = false;
+    envelope.build();
+
+    assert!(envelope.tick_values.is_empty());
+    assert_eq!(envelope.sustain_tick, None);
+    assert_eq!(envelope.loop_end_tick, None);
+}
+
+#[test]
+fn sustain_holds_until_release() {
+    let envelope = make_envelope(Some(2), None);
+
+    assert_eq!(play(&envelope, 8, None), [0, 1, 2, 3, 4, 4, 4, 4]);
+
+    // Sustain point is reached once more after key-off
+    assert_eq!(
+        play(&envelope, 11, Some(6)),
+        [0, 1, 2, 3, 4, 4, 4, 5, 6, 7, 8]
+    );
+}
+
+#[test]
+fn sustain_on_first_point() {
+    let envelope = make_envelope(Some(0), None);
+
+    assert_eq!(play(&envelope, 4, None), [0, 0, 0, 0]);
+    assert_eq!(play(&envelope, 6, Some(2)), [0, 0, 0, 1, 2, 3]);
+}
+
+#[test]
+fn loop_while_held() {
+    let envelope = make_envelope(None, Some((1, 3)));
+
+    // Loop end tick shows loop start value
+    assert_eq!(
+        play(&envelope, 12, None),
+        [0, 1, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3]
+    );
+
+    // Key-off doesn't change looping
+    assert_eq!(play(&envelope, 12, Some(3)), play(&envelope, 12, None));
+}
+
+#[test]
+fn sustain_inside_loop() {
+    let envelope = make_envelope(Some(2), Some((1, 3)));
+
+    assert_eq!(play(&envelope, 8, None), [0, 1, 2, 3, 4, 4, 4, 4]);
+
+    assert_eq!(
+        play(&envelope, 12, Some(6)),
+        [0, 1, 2, 3, 4, 4, 4, 5, 2, 3, 4, 5]
+    );
+}
+
+#[test]
+fn sustain_before_loop() {
+    let envelope = make_envelope(Some(1), Some((2, 4)));
+
+    assert_eq!(play(&envelope, 6, None), [0, 1, 2, 2, 2, 2]);
+
+    assert_eq!(
+        play(&envelope, 12, Some(4)),
+        [0, 1, 2, 2, 2, 3, 4, 5, 6, 7, 4, 5]
+    );
+}
+
+#[test]
+fn sustain_after_loop_is_never_reached() {
+    let envelope = make_envelope(Some(4), Some((0, 2)));
+
+    assert_eq!(play(&envelope, 10, None), [0, 1, 2, 3, 0, 1, 2, 3, 0, 1]);
+}
+
+#[test]
+fn sustain_on_loop_end() {
+    let envelope = make_envelope(Some(3), Some((1, 3)));
+
+    // Loop end is held, not jumped over
+    assert_eq!(play(&envelope, 9, None), [0, 1, 2, 3, 4, 5, 6, 6, 6]);
+
+    // Key-off reaches loop end again right away and jumps back to loop start
+    assert_eq!(
+        play(&envelope, 14, Some(8)),
+        [0, 1, 2, 3, 4, 5, 6, 6, 2, 3, 4, 5, 2, 3]
+    );
+}
+
+#[test]
+fn sustain_on_loop_start() {
+    let envelope = make_envelope(Some(1), Some((1, 3)));
+
+    assert_eq!(play(&envelope, 5, None), [0, 1, 2, 2, 2]);
+
+    assert_eq!(
+        play(&envelope, 12, Some(5)),
+        [0, 1, 2, 2, 2, 2, 3, 4, 5, 2, 3, 4]
+    );
+}
+
+#[test]
+fn zero_length_loop() {
+    let envelope = make_envelope(None, Some((2, 2)));
+
+    // Loop end jumps to itself, so the envelope just continues
+    assert_eq!(play(&envelope, 11, None), [0, 1, 2, 3, 4, 5, 6, 7, 8, 8, 8]);
+}
+
+#[test]
+fn invalid_loop_is_ignored() {
+    let envelope = make_envelope(Some(7), Some((1, 9)));
+
+    assert_eq!(envelope.sustain_tick, None);
+    assert_eq!(envelope.loop_start_tick, None);
+    assert_eq!(play(&envelope, 11, None), [0, 1, 2, 3, 4, 5, 6, 7, 8, 8, 8]);
+}
@@ -0,0 +1,584 @@
+use xm_player::{LoadError, LoopType, Module, NewNoteAction, Player};
+
+// Header flags
+const STEREO: u16 = 0x01;
+const USE_INSTRUMENTS: u16 = 0x04;
+const LINEAR_SLIDES: u16 = 0x08;
+
+// Sample flags & convert flags
+const HAS_DATA: u8 = 0x01;
+const IS_16BIT: u8 = 0x02;
+const COMPRESSED: u8 = 0x08;
+const LOOP: u8 = 0x10;
+const SIGNED: u8 = 0x01;
+const IT215: u8 = 0x04;
+
+struct ItSample {
+    flags: u8,
+    convert_flags: u8,
+    length: u32,
+    loop_start: u32,
+    loop_end: u32,
+    c5_speed: u32,
+    volume: u8,
+    global_volume: u8,
+    panning: u8,
+    // Speed, depth, sweep and waveform
+    vibrato: [u8; 4],
+    data: Vec<u8>,
+}
+
+impl ItSample {
+    // 8-bit signed sample with given data
+    fn new(data: &[u8]) -> ItSample {
+        ItSample {
+            flags: HAS_DATA,
+            convert_flags: SIGNED,
+            length: data.len() as u32,
+            loop_start: 0,
+            loop_end: 0,
+            c5_speed: 8363,
+            volume: 64,
+            global_volume: 64,
+            panning: 32,
+            vibrato: [0; 4],
+            data: data.to_vec(),
+        }
+    }
+}
+
+struct ItModule {
+    flags: u16,
+    compatible_version: u16,
+    global_volume: u8,
+    orders: Vec<u8>,
+    channel_panning: [u8; 64],
+    instruments: Vec<Vec<u8>>,
+    samples: Vec<ItSample>,
+    // Number of rows and packed data, end of row markers for remaining rows are added
+    patterns: Vec<(u16, Vec<u8>)>,
+}
+
+impl ItModule {
+    fn new() -> ItModule {
+        ItModule {
+            flags: STEREO | LINEAR_SLIDES,
+            compatible_version: 0x0214,
+            global_volume: 128,
+            orders: vec![0, 255],
+            channel_panning: [32; 64],
+            instruments: Vec::new(),
+            samples: Vec::new(),
+            patterns: vec![(64, Vec::new())],
+        }
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut data = b"IMPM".to_vec();
+        data.extend_from_slice(b"synthetic module\0\0\0\0\0\0\0\0\0\0");
+        data.extend_from_slice(&[4, 16]);
+
+        for count in [
+            self.orders.len(),
+            self.instruments.len(),
+            self.samples.len(),
+            self.patterns.len(),
+        ] {
+            data.extend_from_slice(&(count as u16).to_le_bytes());
+        }
+
+        data.extend_from_slice(&0x0214u16.to_le_bytes());
+        data.extend_from_slice(&self.compatible_version.to_le_bytes());
+        data.extend_from_slice(&self.flags.to_le_bytes());
+
+        // Special flags, global & mix volume, speed, tempo, separation, pitch wheel depth,
+        // message length & offset and reserved
+        data.extend_from_slice(&[0, 0, self.global_volume, 48, 3, 150, 128, 0]);
+        data.extend_from_slice(&[0; 10]);
+
+        data.extend_from_slice(&self.channel_panning);
+        data.extend_from_slice(&[64; 64]);
+        data.extend_from_slice(&self.orders);
+
+        let num_pointers = self.instruments.len() + self.samples.len() + self.patterns.len();
+        let mut pointer_pos = data.len();
+        data.resize(pointer_pos + num_pointers * 4, 0);
+
+        let mut add_pointer = |data: &mut Vec<u8>| {
+            let pointer = (data.len() as u32).to_le_bytes();
+            data[pointer_pos..pointer_pos + 4].copy_from_slice(&pointer);
+            pointer_pos += 4;
+        };
+
+        for instrument in &self.instruments {
+            add_pointer(&mut data);
+            data.extend_from_slice(instrument);
+        }
+
+        let mut data_pointer_pos = Vec::new();
+        for sample in &self.samples {
+            add_pointer(&mut data);
+            data.extend_from_slice(b"IMPS");
+            data.extend_from_slice(&[0; 13]);
+            data.extend_from_slice(&[sample.global_volume, sample.flags, sample.volume]);
+            data.extend_from_slice(format!("{:\0<26}", "sample").as_bytes());
+            data.extend_from_slice(&[sample.convert_flags, sample.panning]);
+
+            for value in [
+                sample.length,
+                sample.loop_start,
+                sample.loop_end,
+                sample.c5_speed,
+                0,
+                0,
+                0,
+            ] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+
+            data_pointer_pos.push(data.len() - 4);
+            data.extend_from_slice(&sample.vibrato);
+        }
+
+        for (num_rows, packed) in &self.patterns {
+            add_pointer(&mut data);
+            let length = packed.len() + *num_rows as usize;
+            data.extend_from_slice(&(length as u16).to_le_bytes());
+            data.extend_from_slice(&num_rows.to_le_bytes());
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(packed);
+            data.resize(data.len() + *num_rows as usize, 0);
+        }
+
+        for (sample, pos) in self.samples.iter().zip(data_pointer_pos) {
+            let pointer = (data.len() as u32).to_le_bytes();
+            data[pos..pos + 4].copy_from_slice(&pointer);
+            data.extend_from_slice(&sample.data);
+        }
+
+        data
+    }
+}
+
+// New format instrument playing `sample_number` on all notes, with volume envelope
+// given as (tick, value) points and sustain point
+fn it_instrument(
+    sample_number: u8,
+    fadeout: u16,
+    new_note_action: u8,
+    envelope: &[(u16, i8)],
+    sustain: Option<u8>,
+) -> Vec<u8> {
+    let mut data = b"IMPI".to_vec();
+    data.extend_from_slice(&[0; 13]);
+    data.extend_from_slice(&[new_note_action, 0, 0]);
+    data.extend_from_slice(&fadeout.to_le_bytes());
+    data.extend_from_slice(&[0; 10]);
+    data.extend_from_slice(format!("{:\0<26}", "instrument").as_bytes());
+    data.extend_from_slice(&[0; 6]);
+
+    for note in 0..120 {
+        data.extend_from_slice(&[note, sample_number]);
+    }
+
+    // Volume envelope, then empty panning & pitch envelopes
+    let flags = if envelope.is_empty() { 0 } else { 1 } | if sustain.is_some() { 4 } else { 0 };
+    let sustain = sustain.unwrap_or(0);
+    data.extend_from_slice(&[flags, envelope.len() as u8, 0, 0, sustain, sustain]);
+    for i in 0..25 {
+        let (tick, value) = envelope.get(i).copied().unwrap_or_default();
+        data.push(value as u8);
+        data.extend_from_slice(&tick.to_le_bytes());
+    }
+    data.push(0);
+
+    for _ in 0..2 {
+        data.extend_from_slice(&[0; 82]);
+    }
+
+    data.extend_from_slice(&[0; 4]);
+    data
+}
+
+// Packs (value, width) pairs into a bitstream, least significant bit first, and
+// prefixes it with block length like in compressed sample data
+fn compressed_block(values: &[(u32, u32)]) -> Vec<u8> {
+    let mut bits = Vec::new();
+    for &(value, width) in values {
+        bits.extend((0..width).map(|i| (value >> i) & 1 != 0));
+    }
+
+    let bytes: Vec<u8> = bits
+        .chunks(8)
+        .map(|chunk| (0..chunk.len()).fold(0, |byte, i| byte | (chunk[i] as u8) << i))
+        .collect();
+
+    let mut data = (bytes.len() as u16).to_le_bytes().to_vec();
+    data.extend_from_slice(&bytes);
+    data
+}
+
+fn load(it: &ItModule) -> Module {
+    Module::from_bytes(&it.build()).unwrap()
+}
+
+#[test]
+fn header() {
+    let mut it = ItModule::new();
+    it.orders = vec![0, 254, 1, 255, 0];
+    it.patterns.push((32, Vec::new()));
+    it.channel_panning[0] = 0;
+    it.channel_panning[1] = 64;
+    it.channel_panning[2] = 0x80;
+
+    // Notes on third channel, which is disabled, and fourth one
+    it.patterns[0].1 = vec![0x83, 0x01, 60, 0x84, 0x01, 60, 0];
+
+    let module = load(&it);
+    assert_eq!(module.name, "synthetic module");
+    assert_eq!(module.tracker, "Impulse Tracker");
+    assert_eq!((module.tempo, module.bpm), (3, 150));
+    assert!(module.linear_freq_table);
+
+    // Skip marker is removed, song ends at end marker
+    assert_eq!(module.pattern_order, [0, 1]);
+    assert_eq!(module.patterns[1].num_rows, 32);
+
+    // Channels up to the last one used, disabled channel is muted
+    assert_eq!(module.num_channels, 4);
+    assert_eq!(module.channel_panning, [0, 255, 128, 127]);
+    assert_eq!(module.patterns[0].channels[2][0].note, 0x80);
+    assert_eq!(module.patterns[0].channels[3][0].note, 49);
+}
+
+#[test]
+fn global_volume() {
+    let mut it = ItModule::new();
+    assert_eq!(load(&it).global_volume, 64);
+
+    // IT global volume goes up to 128, values past it are clamped
+    it.global_volume = 48;
+    let module = load(&it);
+    assert_eq!(module.global_volume, 24);
+    assert_eq!(Player::new(&module, 48000).get_global_volume(), 24);
+
+    it.global_volume = 200;
+    assert_eq!(load(&it).global_volume, 64);
+}
+
+#[test]
+fn pattern_rows() {
+    let mut it = ItModule::new();
+
+    // Note, instrument, volume & effect, then the same values again through mask bits.
+    // Note off and fine volume slide down.
+    it.patterns[0].1 = vec![
+        0x81, 0x0F, 60, 2, 32, 4, 0x04, 0, //
+        0x81, 0xF0, 0, //
+        0x81, 0x09, 255, 4, 0xF3, 0,
+    ];
+
+    let module = load(&it);
+    let rows: Vec<_> = (0..3)
+        .map(|i| module.patterns[0].get_channel_row(0, i))
+        .collect();
+
+    assert_eq!(
+        (rows[0].note, rows[0].instrument, rows[0].volume),
+        (49, 2, 0x30)
+    );
+    assert_eq!((rows[0].effect_type, rows[0].effect_param), (0x0A, 0x04));
+
+    assert_eq!(
+        (rows[1].note, rows[1].instrument, rows[1].volume),
+        (49, 2, 0x30)
+    );
+    assert_eq!((rows[1].effect_type, rows[1].effect_param), (0x0A, 0x04));
+
+    assert_eq!(rows[2].note, 97);
+    assert_eq!((rows[2].effect_type, rows[2].effect_param), (0x0E, 0xB3));
+}
+
+#[test]
+fn it_effects() {
+    let effects = [
+        // Global volume and its slides are halved, slides round up. No fine slides in XM.
+        ((b'V', 0x80), (0x10, 0x40)),
+        ((b'W', 0x21), (0x11, 0x11)),
+        ((b'W', 0x30), (0x11, 0x20)),
+        ((b'W', 0x05), (0x11, 0x03)),
+        ((b'W', 0x00), (0x11, 0x00)),
+        ((b'W', 0xF2), (0x00, 0x00)),
+        ((b'W', 0x2F), (0x00, 0x00)),
+        // Panning slide directions are swapped
+        ((b'P', 0x12), (0x19, 0x21)),
+        ((b'X', 0x40), (0x08, 0x40)),
+    ];
+
+    let mut it = ItModule::new();
+    for ((effect, param), _) in effects {
+        it.patterns[0]
+            .1
+            .extend_from_slice(&[0x81, 0x08, effect - b'A' + 1, param, 0]);
+    }
+
+    let module = load(&it);
+    for (i, (_, expected)) in effects.iter().enumerate() {
+        let row = module.patterns[0].get_channel_row(0, i);
+        assert_eq!((row.effect_type, row.effect_param), *expected, "row {}", i);
+    }
+}
+
+#[test]
+fn instruments() {
+    let mut it = ItModule::new();
+    it.flags |= USE_INSTRUMENTS;
+    it.samples.push(ItSample::new(&[0, 1]));
+    it.samples.push(ItSample::new(&[2, 3]));
+    it.samples[1].vibrato = [8, 4, 2, 1];
+    it.instruments.push(it_instrument(
+        2,
+        16,
+        2,
+        &[(0, 64), (10, 32), (20, 0)],
+        Some(1),
+    ));
+
+    let module = load(&it);
+    assert_eq!(module.num_instruments, 1);
+
+    // Instrument only keeps samples it plays
+    let instrument = &module.instruments[0];
+    assert_eq!(instrument.name, "instrument");
+    assert_eq!(instrument.samples.len(), 1);
+    assert_eq!(instrument.samples[0].data, [32, 48]);
+    assert_eq!(instrument.sample_keymap, vec![0; 96]);
+    assert!(matches!(instrument.new_note_action, NewNoteAction::NoteOff));
+
+    let envelope = &instrument.volume_envelope;
+    assert_eq!(envelope.points, [(0, 64), (10, 32), (20, 0)]);
+    assert!(envelope.enabled && envelope.sustain_enabled);
+    assert_eq!(envelope.sustain_tick, Some(10));
+    assert_eq!(envelope.fadeout, 16 * 64);
+
+    // Auto-vibrato comes from the sample, ramp down becomes XM ramp down
+    assert_eq!(
+        (
+            instrument.vibrato_rate,
+            instrument.vibrato_depth,
+            instrument.vibrato_sweep,
+            instrument.vibrato_type
+        ),
+        (8, 4, 2, 2)
+    );
+}
+
+#[test]
+fn sample_mode() {
+    let mut it = ItModule::new();
+    let mut sample = ItSample::new(&[0, 10, 20, 30]);
+    sample.flags |= LOOP;
+    sample.loop_start = 1;
+    sample.loop_end = 3;
+    sample.c5_speed = 16726;
+    sample.volume = 64;
+    sample.global_volume = 32;
+    sample.panning = 0x80 | 64;
+    it.samples.push(sample);
+
+    let module = load(&it);
+    assert_eq!(module.instruments.len(), 1);
+
+    let sample = &module.instruments[0].samples[0];
+    assert_eq!(sample.data, [0, 160, 320, 480]);
+    assert!(matches!(sample.loop_type, LoopType::Forward));
+    assert_eq!((sample.loop_start, sample.loop_end), (1.0, 3.0));
+
+    // Twice the middle C frequency is an octave up, global volume scales default volume
+    assert_eq!((sample.relative_note, sample.finetune), (12, 0));
+    assert_eq!(sample.volume, 32);
+    assert!(sample.has_panning);
+    assert_eq!(sample.panning, 255);
+}
+
+#[test]
+fn unsigned_and_16bit_samples() {
+    let mut it = ItModule::new();
+
+    let mut sample = ItSample::new(&[0x80, 0x81, 0x7F]);
+    sample.convert_flags = 0;
+    it.samples.push(sample);
+
+    let mut sample = ItSample::new(&[0x34, 0x12, 0xFF, 0xFF]);
+    sample.flags |= IS_16BIT;
+    sample.length = 2;
+    it.samples.push(sample);
+
+    let module = load(&it);
+    assert_eq!(module.instruments[0].samples[0].data, [0, 16, -16]);
+    assert_eq!(module.instruments[1].samples[0].data, [0x1234, -1]);
+}
+
+// Deltas of 8-bit samples, with width changes through all three methods
+fn compressed_8bit() -> Vec<u8> {
+    compressed_block(&[
+        // Width 9: bottom 8 bits are the value
+        (1, 9),
+        (2, 9),
+        (0xFD, 9),
+        // Top bit set: width becomes 3 + 1
+        (0x103, 9),
+        (5, 4),
+        (0b1110, 4),
+        // Single one at top: width in next 3 bits, 5 + 1 is one more as it's not below 4
+        (0b1000, 4),
+        (5, 3),
+        (0x76, 7),
+        // Value near top of the range: 67 - 59 = 8, one more as it's not below 7
+        (67, 7),
+        (20, 9),
+    ])
+}
+
+#[test]
+fn compressed_8bit_samples() {
+    let mut it = ItModule::new();
+    for convert_flags in [SIGNED, SIGNED | IT215] {
+        let mut sample = ItSample::new(&compressed_8bit());
+        sample.flags |= COMPRESSED;
+        sample.convert_flags = convert_flags;
+        sample.length = 7;
+        it.samples.push(sample);
+    }
+
+    let module = load(&it);
+    let samples: Vec<Vec<i16>> = module
+        .instruments
+        .iter()
+        .map(|instrument| instrument.samples[0].data.iter().map(|v| v / 16).collect())
+        .collect();
+
+    // IT 2.14 integrates deltas once, IT 2.15 twice
+    assert_eq!(samples[0], [1, 3, 0, 5, 3, -7, 13]);
+    assert_eq!(samples[1], [1, 4, 4, 9, 12, 5, 18]);
+}
+
+#[test]
+fn compressed_16bit_samples() {
+    let block = compressed_block(&[
+        // Width 17: bottom 16 bits are the value
+        (1000, 17),
+        (-2000i32 as u32 & 0xFFFF, 17),
+        (30000, 17),
+        (10000, 17),
+        // Top bit set: width becomes 4 + 1
+        (0x10004, 17),
+        (0b11101, 5),
+    ]);
+
+    let mut it = ItModule::new();
+    for convert_flags in [SIGNED, SIGNED | IT215] {
+        let mut sample = ItSample::new(&block);
+        sample.flags |= COMPRESSED | IS_16BIT;
+        sample.convert_flags = convert_flags;
+        sample.length = 5;
+        it.samples.push(sample);
+    }
+
+    let module = load(&it);
+
+    // Values wrap around at 16 bits
+    assert_eq!(
+        module.instruments[0].samples[0].data,
+        [1000, -1000, 29000, -26536, -26539]
+    );
+    assert_eq!(
+        module.instruments[1].samples[0].data,
+        [1000, 0, 29000, 2464, -24075]
+    );
+}
+
+#[test]
+fn compressed_sample_block_ending_early() {
+    // Block runs out after three values, rest of it is silent
+    let mut sample = ItSample::new(&compressed_block(&[(1, 9), (2, 9), (3, 9)]));
+    sample.flags |= COMPRESSED;
+    sample.length = 5;
+
+    let mut it = ItModule::new();
+    it.samples.push(sample);
+
+    let module = load(&it);
+    assert_eq!(module.instruments[0].samples[0].data, [16, 48, 96, 0, 0]);
+}
+
+#[test]
+fn truncated_sample_data() {
+    let mut it = ItModule::new();
+    it.samples.push(ItSample::new(&[1, 2, 3, 4]));
+
+    let mut data = it.build();
+    data.truncate(data.len() - 1);
+
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedSample {
+            instrument: None,
+            sample: 0,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(data.len()));
+
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert_eq!(module.instruments[0].samples[0].data, [16, 32, 48]);
+}
+
+#[test]
+fn note_cut_silences_sustained_note() {
+    // Looping sample and volume envelope held at its first point, no fadeout. Returns
+    // second row, its last output sample and the row saved as XM.
+    let render = |note: u8| {
+        let mut it = ItModule::new();
+        it.flags |= USE_INSTRUMENTS;
+
+        let mut sample = ItSample::new(&[64; 4]);
+        sample.flags |= LOOP;
+        sample.loop_end = 4;
+        it.samples.push(sample);
+        it.instruments
+            .push(it_instrument(1, 0, 0, &[(0, 64), (10, 64)], Some(0)));
+
+        it.patterns[0].1 = vec![0x81, 0x03, 60, 1, 0, 0x81, 0x01, note, 0];
+        let module = load(&it);
+        let row = module.patterns[0].get_channel_row(0, 1);
+
+        let mut player = Player::new(&module, 1000);
+        let mut buffer = vec![0i16; 16 * 2 * 6];
+        player.render(&mut buffer);
+
+        let mut saved = Vec::new();
+        module.save_xm(&mut saved).unwrap();
+        let saved_row = Module::from_bytes(&saved).unwrap().patterns[0].get_channel_row(0, 1);
+
+        (
+            row.note,
+            buffer[buffer.len() - 2..].to_vec(),
+            (saved_row.note, saved_row.volume),
+        )
+    };
+
+    // Note off only releases sustain, note cut stops the note. XM has no note cut,
+    // it's saved as key-off with zero volume.
+    let (note, output, saved_row) = render(255);
+    assert_eq!(note, 97);
+    assert_ne!(output, [0, 0]);
+    assert_eq!(saved_row, (97, 0));
+
+    let (note, output, saved_row) = render(254);
+    assert_eq!(note, 98);
+    assert_eq!(output, [0, 0]);
+    assert_eq!(saved_row, (97, 0x10));
+}
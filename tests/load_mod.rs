@@ -0,0 +1,255 @@
+use xm_player::{LoadError, LoopType, Module};
+
+// Offset of format signature in 31 sample modules
+const SIGNATURE_POS: usize = 1080;
+
+#[derive(Clone, Copy, Default)]
+struct ModSample {
+    // All in 16-bit words, like in the file
+    length: u16,
+    finetune: u8,
+    volume: u8,
+    loop_start: u16,
+    loop_length: u16,
+}
+
+// Pattern cell with 12-bit period, sample number split into nibbles and effect
+fn cell(period: u16, sample: u8, effect_type: u8, effect_param: u8) -> [u8; 4] {
+    [
+        (sample & 0xF0) | (period >> 8) as u8,
+        period as u8,
+        (sample << 4) | effect_type,
+        effect_param,
+    ]
+}
+
+// Module with single pattern, `cells` fill its first row. Signature `None` makes an
+// original 15 sample Soundtracker module.
+fn make_mod(
+    signature: Option<&[u8; 4]>,
+    num_channels: usize,
+    samples: &[ModSample],
+    cells: &[[u8; 4]],
+) -> Vec<u8> {
+    let num_samples = if signature.is_some() { 31 } else { 15 };
+
+    let mut data = b"synthetic module\0\0\0\0".to_vec();
+    for i in 0..num_samples {
+        let sample = samples.get(i).copied().unwrap_or_default();
+
+        data.extend_from_slice(format!("{:<22}", format!("sample {}", i)).as_bytes());
+        data.extend_from_slice(&sample.length.to_be_bytes());
+        data.push(sample.finetune);
+        data.push(sample.volume);
+        data.extend_from_slice(&sample.loop_start.to_be_bytes());
+        data.extend_from_slice(&sample.loop_length.to_be_bytes());
+    }
+
+    // Song length, restart position and pattern order
+    data.extend_from_slice(&[1, 127]);
+    data.extend_from_slice(&[0; 128]);
+
+    if let Some(signature) = signature {
+        assert_eq!(data.len(), SIGNATURE_POS);
+        data.extend_from_slice(signature);
+    }
+
+    let mut pattern = vec![0; 64 * num_channels * 4];
+    for (i, cell) in cells.iter().enumerate() {
+        pattern[i * 4..i * 4 + 4].copy_from_slice(cell);
+    }
+    data.extend_from_slice(&pattern);
+
+    // Sample data counts up from zero
+    for sample in samples {
+        data.extend((0..sample.length as usize * 2).map(|i| i as u8));
+    }
+
+    data
+}
+
+#[test]
+fn signatures() {
+    for (signature, tracker, num_channels) in [
+        (b"M.K.", "ProTracker", 4),
+        (b"M!K!", "ProTracker", 4),
+        (b"FLT4", "StarTrekker", 4),
+        (b"6CHN", "FastTracker", 6),
+        (b"8CHN", "FastTracker", 8),
+        (b"12CH", "FastTracker", 12),
+        (b"32CH", "FastTracker", 32),
+    ] {
+        let data = make_mod(Some(signature), num_channels, &[], &[]);
+        let module = Module::from_bytes(&data).unwrap();
+
+        assert_eq!(module.tracker, tracker);
+        assert_eq!(module.num_channels, num_channels);
+        assert_eq!(module.instruments.len(), 31);
+        assert_eq!(module.patterns.len(), 1);
+        assert_eq!(module.patterns[0].num_rows, 64);
+        assert_eq!(module.patterns[0].channels.len(), num_channels);
+        assert_eq!(module.name, "synthetic module");
+        assert!(!module.linear_freq_table);
+    }
+}
+
+#[test]
+fn soundtracker_module_without_signature() {
+    let sample = ModSample {
+        length: 8,
+        volume: 64,
+        loop_start: 4,
+        loop_length: 2,
+        ..ModSample::default()
+    };
+    let data = make_mod(None, 4, &[sample], &[cell(428, 1, 0, 0)]);
+    let module = Module::from_bytes(&data).unwrap();
+
+    assert_eq!(module.tracker, "Soundtracker");
+    assert_eq!(module.num_channels, 4);
+    assert_eq!(module.instruments.len(), 15);
+    assert_eq!(module.patterns[0].channels[0][0].note, 49);
+
+    // Loop start is in bytes, not words
+    let sample = &module.instruments[0].samples[0];
+    assert_eq!(sample.sample_end, 16.0);
+    assert_eq!((sample.loop_start, sample.loop_end), (4.0, 8.0));
+}
+
+#[test]
+fn unknown_signature() {
+    let mut data = make_mod(Some(b"M.K."), 4, &[], &[]);
+    data[SIGNATURE_POS..SIGNATURE_POS + 4].copy_from_slice(b"ABCD");
+
+    // Not a valid Soundtracker module either, volume of the first sample is too high
+    data[20 + 25] = 65;
+
+    assert!(matches!(
+        Module::from_bytes(&data),
+        Err(LoadError::BadMagic { offset: 0 })
+    ));
+}
+
+#[test]
+fn periods_become_notes() {
+    // C-4 is period 428 with zero finetune, other periods are rounded to the closest note
+    let cells = [
+        cell(428, 1, 0, 0),
+        cell(856, 2, 0, 0),
+        cell(214, 0x10, 0, 0),
+        cell(453, 0x1F, 0x0C, 0x20),
+        cell(0, 0, 0x0F, 0x06),
+        cell(1712, 0, 0, 0),
+        cell(4000, 0, 0, 0),
+        cell(20, 0, 0, 0),
+    ];
+    let data = make_mod(Some(b"8CHN"), 8, &[], &cells);
+    let module = Module::from_bytes(&data).unwrap();
+    let rows: Vec<_> = (0..8)
+        .map(|i| module.patterns[0].get_channel_row(i, 0))
+        .collect();
+
+    let notes: Vec<_> = rows.iter().map(|row| row.note).collect();
+    assert_eq!(notes, [49, 37, 61, 48, 0x80, 25, 10, 96]);
+
+    // Sample number is split between first and third byte
+    let instruments: Vec<_> = rows.iter().map(|row| row.instrument).collect();
+    assert_eq!(instruments, [1, 2, 0x10, 0x1F, 0, 0, 0, 0]);
+
+    // Effects are stored as in XM
+    assert_eq!((rows[3].effect_type, rows[3].effect_param), (0x0C, 0x20));
+    assert_eq!((rows[4].effect_type, rows[4].effect_param), (0x0F, 0x06));
+}
+
+#[test]
+fn sample_headers() {
+    let samples = [
+        ModSample {
+            length: 4,
+            finetune: 0x07,
+            volume: 80,
+            loop_start: 1,
+            loop_length: 2,
+        },
+        ModSample {
+            length: 4,
+            finetune: 0x08,
+            volume: 32,
+            loop_start: 0,
+            loop_length: 1,
+        },
+        ModSample {
+            length: 2,
+            finetune: 0x0F,
+            ..ModSample::default()
+        },
+    ];
+    let data = make_mod(Some(b"M.K."), 4, &samples, &[]);
+    let module = Module::from_bytes(&data).unwrap();
+    let sample = |i: usize| &module.instruments[i].samples[0];
+
+    // Signed finetune nibble in 1/8 semitones becomes 1/128 semitones
+    assert_eq!(sample(0).finetune, 112);
+    assert_eq!(sample(1).finetune, -128);
+    assert_eq!(sample(2).finetune, -16);
+
+    assert_eq!(sample(0).volume, 64);
+    assert_eq!(sample(1).volume, 32);
+
+    // Lengths are in words, one word long loop means no loop
+    assert_eq!(sample(0).sample_end, 8.0);
+    assert!(matches!(sample(0).loop_type, LoopType::Forward));
+    assert_eq!((sample(0).loop_start, sample(0).loop_end), (2.0, 6.0));
+    assert!(matches!(sample(1).loop_type, LoopType::None));
+
+    // Sample data follows the patterns, plain signed 8-bit scaled to 16-bit range
+    assert_eq!(sample(0).data, [0, 16, 32, 48, 64, 80, 96, 112]);
+    assert_eq!(sample(2).data, [0, 16, 32, 48]);
+    assert_eq!(module.instruments[0].name, "sample 0");
+}
+
+#[test]
+fn channels_are_panned_like_amiga() {
+    let sample = ModSample {
+        length: 1,
+        ..ModSample::default()
+    };
+    let data = make_mod(Some(b"6CHN"), 6, &[sample], &[]);
+    let module = Module::from_bytes(&data).unwrap();
+
+    assert_eq!(module.channel_panning, [0, 255, 255, 0, 0, 255]);
+    assert!(!module.instruments[0].samples[0].has_panning);
+}
+
+#[test]
+fn truncated_sample_data() {
+    let sample = ModSample {
+        length: 4,
+        ..ModSample::default()
+    };
+    let mut data = make_mod(Some(b"M.K."), 4, &[sample, sample], &[]);
+    data.truncate(data.len() - 2);
+
+    // Samples are numbered on their own, without instruments
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedSample {
+            instrument: None,
+            sample: 1,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(data.len()));
+    assert_eq!(
+        err.to_string(),
+        format!("Truncated sample 1 at offset {:#x}", data.len())
+    );
+    assert!(err.is_recoverable_truncation());
+
+    // Lenient loading keeps what was there
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert_eq!(module.instruments[0].samples[0].data.len(), 8);
+    assert_eq!(module.instruments[1].samples[0].data.len(), 6);
+}
@@ -0,0 +1,237 @@
+use xm_player::{Module, Player};
+
+struct S3mModule {
+    global_volume: u8,
+    speed: u8,
+    stereo: bool,
+    // 0-7 left channels, 8-15 right ones, anything else is not loaded
+    channel_settings: [u8; 32],
+    orders: Vec<u8>,
+    panning_table: Option<[u8; 32]>,
+    // Packed rows, end of row markers for remaining rows are added
+    patterns: Vec<Vec<u8>>,
+}
+
+impl S3mModule {
+    fn new() -> S3mModule {
+        let mut channel_settings = [255; 32];
+        channel_settings[..4].copy_from_slice(&[0, 8, 9, 1]);
+
+        S3mModule {
+            global_volume: 64,
+            speed: 4,
+            stereo: true,
+            channel_settings,
+            orders: vec![0, 255],
+            panning_table: None,
+            patterns: vec![Vec::new()],
+        }
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut data = format!("{:\0<28}", "synthetic module").into_bytes();
+        data.extend_from_slice(&[0x1A, 16, 0, 0]);
+
+        for count in [self.orders.len(), 0, self.patterns.len()] {
+            data.extend_from_slice(&(count as u16).to_le_bytes());
+        }
+
+        // Flags, tracker version and signed samples
+        data.extend_from_slice(&[0, 0, 0x20, 0x13, 1, 0]);
+        data.extend_from_slice(b"SCRM");
+
+        // Global volume, speed, tempo, master volume, ultra click removal and default panning
+        let master_volume = if self.stereo { 0xB0 } else { 0x30 };
+        let default_panning = if self.panning_table.is_some() { 252 } else { 0 };
+        data.extend_from_slice(&[self.global_volume, self.speed, 140, master_volume, 0]);
+        data.push(default_panning);
+        data.extend_from_slice(&[0; 10]);
+
+        data.extend_from_slice(&self.channel_settings);
+        data.extend_from_slice(&self.orders);
+
+        let mut pointer_pos = data.len();
+        data.resize(pointer_pos + self.patterns.len() * 2, 0);
+
+        if let Some(panning_table) = self.panning_table {
+            data.extend_from_slice(&panning_table);
+        }
+
+        // Patterns are 16-byte aligned, pointers count in 16 byte units
+        for pattern in &self.patterns {
+            data.resize(data.len().next_multiple_of(16), 0);
+
+            let pointer = ((data.len() / 16) as u16).to_le_bytes();
+            data[pointer_pos..pointer_pos + 2].copy_from_slice(&pointer);
+            pointer_pos += 2;
+
+            data.extend_from_slice(&((pattern.len() + 66) as u16).to_le_bytes());
+            data.extend_from_slice(pattern);
+            data.extend_from_slice(&[0; 64]);
+        }
+
+        data
+    }
+}
+
+fn load(s3m: &S3mModule) -> Module {
+    Module::from_bytes(&s3m.build()).unwrap()
+}
+
+// Effect letter and parameter on the first channel, followed by end of row
+fn effect_row(effect: u8, param: u8) -> [u8; 4] {
+    [0x80, effect - b'A' + 1, param, 0]
+}
+
+#[test]
+fn header() {
+    let mut s3m = S3mModule::new();
+    s3m.global_volume = 48;
+
+    let module = load(&s3m);
+    assert_eq!(module.name, "synthetic module");
+    assert_eq!(module.tracker, "Scream Tracker 3");
+    assert_eq!((module.tempo, module.bpm), (4, 140));
+    assert!(!module.linear_freq_table);
+
+    // Song starts at header global volume, values past 64 are clamped
+    assert_eq!(module.global_volume, 48);
+    assert_eq!(Player::new(&module, 48000).get_global_volume(), 48);
+
+    s3m.global_volume = 100;
+    assert_eq!(load(&s3m).global_volume, 64);
+
+    // Zero speed means default speed
+    s3m.speed = 0;
+    assert_eq!(load(&s3m).tempo, 6);
+}
+
+#[test]
+fn notes() {
+    let mut s3m = S3mModule::new();
+
+    // Octave in upper nibble, note in lower one, followed by instrument
+    for note in [0x40, 0x00, 0x9B, 254, 255] {
+        s3m.patterns[0].extend_from_slice(&[0x20, note, 1, 0]);
+    }
+
+    // Note cut stays note cut, 255 is no note
+    let module = load(&s3m);
+    let notes: Vec<_> = (0..5)
+        .map(|i| module.patterns[0].get_channel_row(0, i).note)
+        .collect();
+    assert_eq!(notes, [49, 1, 96, 98, 0x80]);
+}
+
+#[test]
+fn order_markers() {
+    let mut s3m = S3mModule::new();
+    s3m.orders = vec![0, 254, 1, 255, 0];
+    s3m.patterns.push(Vec::new());
+
+    // Jumps to the skipped position, pattern 1, past the end marker and past the orders
+    for (effect, param) in [(b'B', 1), (b'B', 2), (b'B', 4), (b'B', 5)] {
+        s3m.patterns[0].extend_from_slice(&effect_row(effect, param));
+    }
+
+    // Skip markers are removed and song ends at the end marker
+    let module = load(&s3m);
+    assert_eq!(module.pattern_order, [0, 1]);
+    assert_eq!(module.patterns.len(), 2);
+
+    let jumps: Vec<_> = (0..4)
+        .map(|i| module.patterns[0].get_channel_row(0, i))
+        .map(|row| (row.effect_type, row.effect_param))
+        .collect();
+    assert_eq!(jumps, [(0x0B, 1), (0x0B, 1), (0x0B, 2), (0x0B, 0)]);
+}
+
+#[test]
+fn position_jump_past_256_orders() {
+    let mut s3m = S3mModule::new();
+    s3m.orders = vec![0; 256];
+    s3m.patterns[0].extend_from_slice(&effect_row(b'B', 255));
+
+    let module = load(&s3m);
+    assert_eq!(module.pattern_order.len(), 256);
+
+    let row = module.patterns[0].get_channel_row(0, 0);
+    assert_eq!((row.effect_type, row.effect_param), (0x0B, 255));
+}
+
+#[test]
+fn effects_become_xm_effects() {
+    let effects = [
+        // Speed, zero speed is ignored and speeds past 0x1F are not BPM
+        ((b'A', 0x06), (0x0F, 0x06)),
+        ((b'A', 0x00), (0x00, 0x00)),
+        ((b'A', 0x1F), (0x0F, 0x1F)),
+        ((b'A', 0x20), (0x1C, 0x20)),
+        ((b'A', 0xFF), (0x1C, 0xFF)),
+        ((b'C', 0x10), (0x0D, 0x10)),
+        // Volume slides, fine ones have F in either nibble
+        ((b'D', 0x04), (0x0A, 0x04)),
+        ((b'D', 0xF3), (0x0E, 0xB3)),
+        ((b'D', 0x3F), (0x0E, 0xA3)),
+        // Portamento down & up with fine and extra fine variants
+        ((b'E', 0x10), (0x02, 0x10)),
+        ((b'E', 0xF2), (0x0E, 0x22)),
+        ((b'E', 0xE2), (0x21, 0x22)),
+        ((b'F', 0x10), (0x01, 0x10)),
+        ((b'F', 0xF2), (0x0E, 0x12)),
+        ((b'F', 0xE2), (0x21, 0x12)),
+        ((b'G', 0x20), (0x03, 0x20)),
+        // Vibrato and fine vibrato, which keeps its own effect
+        ((b'H', 0x48), (0x04, 0x48)),
+        ((b'U', 0x48), (0x1E, 0x48)),
+        ((b'J', 0x37), (0x00, 0x37)),
+        ((b'Q', 0x21), (0x1B, 0x21)),
+        // Specials
+        ((b'S', 0x11), (0x0E, 0x31)),
+        ((b'S', 0x8F), (0x08, 0xFF)),
+        ((b'S', 0xB2), (0x0E, 0x62)),
+        ((b'S', 0xC3), (0x0E, 0xC3)),
+        ((b'S', 0xD2), (0x0E, 0xD2)),
+        ((b'S', 0xF0), (0x00, 0x00)),
+        // Tempo below 32 is not valid
+        ((b'T', 0x1F), (0x00, 0x00)),
+        ((b'T', 0x80), (0x0F, 0x80)),
+        ((b'V', 0x50), (0x10, 0x40)),
+        // Panning in 0x00-0x80 range, surround is not supported
+        ((b'X', 0x40), (0x08, 0x7F)),
+        ((b'X', 0xA4), (0x00, 0x00)),
+        ((b'Z', 0x10), (0x00, 0x00)),
+    ];
+
+    let mut s3m = S3mModule::new();
+    for ((effect, param), _) in effects {
+        s3m.patterns[0].extend_from_slice(&effect_row(effect, param));
+    }
+
+    let module = load(&s3m);
+    for (i, (_, expected)) in effects.iter().enumerate() {
+        let row = module.patterns[0].get_channel_row(0, i);
+        assert_eq!((row.effect_type, row.effect_param), *expected, "row {}", i);
+    }
+}
+
+#[test]
+fn default_channel_panning() {
+    // Disabled channels are dropped, remaining ones are left or right
+    let mut s3m = S3mModule::new();
+    s3m.channel_settings[2] = 0x80 | 9;
+    let module = load(&s3m);
+    assert_eq!(module.num_channels, 3);
+    assert_eq!(module.channel_panning, [0x33, 0xCC, 0x33]);
+
+    // Mono modules play everything in the center
+    s3m.stereo = false;
+    assert_eq!(load(&s3m).channel_panning, [128; 3]);
+
+    // Panning table entries are used when bit 5 is set
+    s3m.stereo = true;
+    let mut panning_table = [0; 32];
+    panning_table[..4].copy_from_slice(&[0x20 | 0x7, 0x0, 0x20, 0x20 | 0xF]);
+    s3m.panning_table = Some(panning_table);
+    assert_eq!(load(&s3m).channel_panning, [0x77, 0xCC, 0xFF]);
+}
@@ -0,0 +1,368 @@
+use std::rc::Rc;
+
+use xm_player::{Instrument, LoadError, Module, Pattern, Player, Row, Sample};
+
+const SONG_LENGTH_POS: usize = 64;
+const NUM_PATTERNS_POS: usize = 70;
+const PATTERN_ORDER_POS: usize = 80;
+const HEADER_SIZE_POS: usize = 60;
+const NUM_CHANNELS_POS: usize = 68;
+
+fn read_song() -> Vec<u8> {
+    std::fs::read(format!("{}/song.xm", env!("CARGO_MANIFEST_DIR"))).unwrap()
+}
+
+fn read_u16(data: &[u8], pos: usize) -> usize {
+    u16::from_le_bytes([data[pos], data[pos + 1]]) as usize
+}
+
+fn read_u32(data: &[u8], pos: usize) -> usize {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize
+}
+
+// Offset of the first instrument, which follows all the patterns
+fn first_instrument_pos(data: &[u8]) -> usize {
+    let mut pos = HEADER_SIZE_POS + read_u32(data, HEADER_SIZE_POS);
+    for _ in 0..read_u16(data, NUM_PATTERNS_POS) {
+        pos += read_u32(data, pos) + read_u16(data, pos + 7);
+    }
+
+    pos
+}
+
+// Offset of the first sample header of the first instrument
+fn first_sample_header_pos(data: &[u8]) -> usize {
+    let instrument_pos = first_instrument_pos(data);
+    assert!(read_u16(data, instrument_pos + 27) > 0);
+
+    instrument_pos + read_u32(data, instrument_pos)
+}
+
+#[test]
+fn missing_patterns_in_order_are_empty() {
+    let mut data = read_song();
+    let num_patterns = read_u16(&data, NUM_PATTERNS_POS);
+    assert!(read_u16(&data, SONG_LENGTH_POS) > 1);
+
+    // Second position points past the stored patterns
+    data[PATTERN_ORDER_POS + 1] = 200;
+
+    let module = Module::from_bytes(&data).unwrap();
+    assert_eq!(module.pattern_order[1], 200);
+    assert_eq!(module.patterns.len(), 201);
+
+    // Stored patterns are loaded as before, others are empty with 64 rows
+    let original = Module::from_bytes(&read_song()).unwrap();
+    assert_eq!(
+        module.patterns[num_patterns - 1].num_rows,
+        original.patterns[num_patterns - 1].num_rows
+    );
+
+    let pattern = &module.patterns[200];
+    assert_eq!(pattern.num_rows, 64);
+    assert_eq!(pattern.channels.len(), module.num_channels);
+    assert!(pattern.channels[0]
+        .iter()
+        .all(|row| row.note == Row::default().note));
+
+    // Player goes through all rows of the empty pattern
+    let mut player = Player::new(&module, 48000);
+    let mut buffer = vec![0i16; 256];
+    let mut last_row = 0;
+    while player.pattern_order_index <= 1 && player.loop_count == 0 {
+        if player.pattern_order_index == 1 {
+            last_row = player.row_index;
+        }
+
+        player.render(&mut buffer);
+    }
+
+    assert_eq!(last_row, 63);
+}
+
+#[test]
+fn names_padded_with_zeros_are_trimmed() {
+    let mut module = Module::from_bytes(&read_song()).unwrap();
+    module.name = "zero\u{1}padded".to_string();
+
+    let mut data = Vec::new();
+    module.save_xm(&mut data).unwrap();
+    assert_eq!(&data[17..37], b"zero\x01padded\0\0\0\0\0\0\0\0\0");
+
+    // Control characters become spaces
+    let module = Module::from_bytes(&data).unwrap();
+    assert_eq!(module.name, "zero padded");
+}
+
+// Plays the whole song once, damaged modules must not make player panic
+fn play_song(module: &Module) {
+    let mut player = Player::new(module, 8000);
+    let mut buffer = vec![0i16; 1024];
+    while player.loop_count == 0 {
+        player.render(&mut buffer);
+    }
+}
+
+#[test]
+fn lenient_loading_of_truncated_pattern() {
+    let mut data = read_song();
+
+    // Data ends inside the packed data of the second pattern
+    let first_pattern_pos = HEADER_SIZE_POS + read_u32(&data, HEADER_SIZE_POS);
+    let second_pattern_pos = first_pattern_pos
+        + read_u32(&data, first_pattern_pos)
+        + read_u16(&data, first_pattern_pos + 7);
+    data.truncate(second_pattern_pos + 20);
+
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedPattern { pattern: 1, .. }
+    ));
+    assert_eq!(err.offset(), Some(data.len()));
+
+    // No instruments could be read, later patterns are empty
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert!(module.instruments.is_empty());
+    assert!(module.num_instruments > 0);
+
+    let original = Module::from_bytes(&read_song()).unwrap();
+    assert_eq!(module.patterns.len(), original.patterns.len());
+    let notes = |module: &Module| -> Vec<u8> {
+        module.patterns[0].channels[0]
+            .iter()
+            .map(|row| row.note)
+            .collect()
+    };
+    assert_eq!(notes(&module), notes(&original));
+    assert_eq!(module.patterns[2].num_rows, 64);
+
+    play_song(&module);
+}
+
+#[test]
+fn lenient_loading_of_truncated_instrument() {
+    let mut data = read_song();
+    let instrument_pos = first_instrument_pos(&data);
+
+    // Data ends inside the name of the first instrument
+    data.truncate(instrument_pos + 10);
+
+    assert!(matches!(
+        Module::from_bytes(&data),
+        Err(LoadError::TruncatedInstrument { instrument: 0, .. })
+    ));
+
+    // Partially read instrument is kept, the rest are missing
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert_eq!(module.instruments.len(), 1);
+    assert!(module.instruments[0].samples.is_empty());
+
+    let original = Module::from_bytes(&read_song()).unwrap();
+    assert_eq!(module.patterns.len(), original.patterns.len());
+
+    play_song(&module);
+}
+
+#[test]
+fn lenient_loading_of_truncated_sample_data() {
+    let mut data = read_song();
+
+    // Data ends inside the sample data of the first instrument, which has one sample
+    let sample_data_pos = first_sample_header_pos(&data) + 40;
+    let sample_length = read_u32(&data, first_sample_header_pos(&data));
+    assert!(sample_length > 50);
+    data.truncate(sample_data_pos + 50);
+
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedSample {
+            instrument: Some(0),
+            sample: 0,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(data.len()));
+
+    // Sample is shortened to the data that is there
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert_eq!(module.instruments.len(), 1);
+
+    let sample = &module.instruments[0].samples[0];
+    assert_eq!(sample.data.len(), 50);
+    assert_eq!(sample.sample_end, 50.0);
+    assert!(sample.loop_end <= 50.0);
+
+    play_song(&module);
+}
+
+// XM image with a single 8-bit sample of given length, which is stored as ADPCM data
+fn adpcm_module(sample_length: usize, adpcm_data: &[u8]) -> Vec<u8> {
+    let mut sample = Sample::default();
+    sample.data = vec![0; sample_length];
+    sample.sample_end = sample_length as f32;
+
+    let module = Module {
+        patterns: vec![Pattern {
+            num_rows: 64,
+            channels: vec![vec![Row::default(); 64]],
+        }],
+        pattern_order: vec![0],
+        instruments: vec![Rc::new(Instrument {
+            samples: vec![Rc::new(sample)],
+            sample_keymap: vec![0; 96],
+            ..Instrument::default()
+        })],
+        num_instruments: 1,
+        num_channels: 1,
+        tempo: 6,
+        bpm: 125,
+        ..Module::default()
+    };
+
+    let mut data = Vec::new();
+    module.save_xm(&mut data).unwrap();
+
+    // Sample data is at the very end, compression type is the 18th byte of its header
+    let data_pos = data.len() - sample_length;
+    data[data_pos - 40 + 17] = 0xAD;
+    data.truncate(data_pos);
+    data.extend_from_slice(adpcm_data);
+
+    data
+}
+
+const ADPCM_DELTAS: [u8; 16] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 0xFF, 0xFE, 0xFD, 0xFC, 0xFB, 0xFA, 0xF9, 0xF8,
+];
+
+#[test]
+fn adpcm_samples() {
+    // Low nibble is decoded first, odd sample count leaves the last high nibble unused
+    let mut adpcm_data = ADPCM_DELTAS.to_vec();
+    adpcm_data.extend_from_slice(&[0x21, 0x43, 0x78]);
+
+    let module = Module::from_bytes(&adpcm_module(5, &adpcm_data)).unwrap();
+    let sample = &module.instruments[0].samples[0];
+    assert_eq!(sample.data, [16, 48, 96, 160, 144]);
+    assert_eq!(sample.sample_end, 5.0);
+}
+
+#[test]
+fn adpcm_sample_truncated() {
+    let mut adpcm_data = ADPCM_DELTAS.to_vec();
+    adpcm_data.extend_from_slice(&[0x21, 0x43, 0x78]);
+
+    // Data ends inside packed nibbles, whole bytes are still decoded
+    let data = adpcm_module(6, &adpcm_data[..18]);
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedSample {
+            instrument: Some(0),
+            sample: 0,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(data.len()));
+
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.instruments[0].samples[0].data, [16, 48, 96, 160]);
+
+    // Nothing is decoded without the whole delta table
+    let data = adpcm_module(6, &adpcm_data[..10]);
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(err, LoadError::TruncatedSample { .. }));
+    assert_eq!(err.offset(), Some(data.len()));
+
+    let module = Module::from_bytes_lenient(&data).unwrap();
+    assert_eq!(module.warnings.len(), 1);
+    assert!(module.instruments[0].samples[0].data.is_empty());
+}
+
+#[test]
+fn header_errors() {
+    let mut data = read_song();
+    data[0] = b'e';
+    assert!(matches!(
+        Module::from_bytes(&data),
+        Err(LoadError::BadMagic { offset: 0 })
+    ));
+
+    let mut data = read_song();
+    data[NUM_CHANNELS_POS] = 0;
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::InvalidHeader {
+            offset: NUM_CHANNELS_POS,
+            field: "number of channels",
+            value: 0,
+        }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Invalid number of channels 0 in module header at offset 0x44"
+    );
+
+    // Ends in the middle of number of patterns
+    let mut data = read_song();
+    data.truncate(NUM_PATTERNS_POS + 1);
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::TruncatedHeader {
+            offset: NUM_PATTERNS_POS
+        }
+    ));
+    assert_eq!(err.offset(), Some(NUM_PATTERNS_POS));
+    assert!(!err.is_recoverable_truncation());
+}
+
+#[test]
+fn sample_errors() {
+    let sample_pos = first_sample_header_pos(&read_song());
+    let flags_pos = sample_pos + 14;
+    let compression_pos = sample_pos + 17;
+
+    let mut data = read_song();
+    data[flags_pos] |= 0x03;
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::InvalidLoopType {
+            instrument: Some(0),
+            sample: 0,
+            loop_type: 3,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(flags_pos));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Invalid loop type 3 in instrument 0, sample 0 at offset {:#x}",
+            flags_pos
+        )
+    );
+
+    // ADPCM is only defined for 8-bit samples
+    let mut data = read_song();
+    data[flags_pos] |= 0x10;
+    data[compression_pos] = 0xAD;
+    let err = Module::from_bytes(&data).err().unwrap();
+    assert!(matches!(
+        err,
+        LoadError::UnsupportedCompression {
+            instrument: Some(0),
+            sample: 0,
+            compression: 0xAD,
+            ..
+        }
+    ));
+    assert_eq!(err.offset(), Some(compression_pos));
+}
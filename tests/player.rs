@@ -0,0 +1,397 @@
+use std::rc::Rc;
+
+use xm_player::{Instrument, LoopType, Module, Pattern, Player, Row, Sample};
+
+// 1000Hz at 125 BPM gives 20 samples per tick
+const SAMPLE_RATE: usize = 1000;
+const TICK_LENGTH: usize = 20 * 2;
+
+fn effect(effect_type: u8, effect_param: u8) -> Row {
+    Row {
+        effect_type,
+        effect_param,
+        ..Row::default()
+    }
+}
+
+fn note(note: u8, instrument: u8) -> Row {
+    Row {
+        note,
+        instrument,
+        ..Row::default()
+    }
+}
+
+// Adds instrument with a constant, looping sample
+fn add_instrument_with_value(module: &mut Module, value: i16) {
+    let mut sample = Sample::default();
+    sample.data = vec![value; 64];
+    sample.loop_type = LoopType::Forward;
+    sample.loop_end = 64.0;
+    sample.sample_end = 64.0;
+    sample.volume = 64;
+
+    module.instruments.push(Rc::new(Instrument {
+        samples: vec![Rc::new(sample)],
+        sample_keymap: vec![0; 96],
+        ..Instrument::default()
+    }));
+}
+
+// Instrument 1 plays a constant, looping sample
+fn add_instrument(module: &mut Module) {
+    add_instrument_with_value(module, 1024);
+}
+
+// Single channel patterns with given effects, other rows are empty
+fn make_module(patterns: &[(usize, &[(usize, Row)])], pattern_order: &[usize]) -> Module {
+    Module {
+        patterns: patterns
+            .iter()
+            .map(|&(num_rows, effects)| {
+                let mut rows = vec![Row::default(); num_rows];
+                for &(row_index, row) in effects {
+                    rows[row_index] = row;
+                }
+
+                Pattern {
+                    num_rows,
+                    channels: vec![rows],
+                }
+            })
+            .collect(),
+        pattern_order: pattern_order.to_vec(),
+        num_channels: 1,
+        tempo: 2,
+        bpm: 125,
+        ..Module::default()
+    }
+}
+
+// (pattern order index, row index) of every row played, until song loops
+fn play_rows(module: &Module) -> Vec<(usize, usize)> {
+    let mut player = Player::new(module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH];
+    let mut result = Vec::new();
+
+    while player.loop_count == 0 {
+        if player.row_tick == 0 {
+            result.push((player.pattern_order_index, player.row_index));
+        }
+
+        player.render(&mut buffer);
+        assert!(result.len() < 1000);
+    }
+
+    result
+}
+
+#[test]
+fn plays_all_rows_in_order() {
+    let module = make_module(&[(2, &[]), (3, &[])], &[1, 0]);
+
+    assert_eq!(play_rows(&module), [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1)]);
+}
+
+#[test]
+fn pattern_break_goes_to_row_of_next_pattern() {
+    // Row is given in decimal, rows past the end start from the beginning
+    let module = make_module(
+        &[
+            (3, &[(0, effect(0x0D, 0x02))]),
+            (12, &[(11, effect(0x0D, 0x12))]),
+        ],
+        &[0, 1],
+    );
+
+    let rows = play_rows(&module);
+    assert_eq!(rows.len(), 11);
+    assert_eq!(rows[..3], [(0, 0), (1, 2), (1, 3)]);
+    assert_eq!(rows[10], (1, 11));
+}
+
+#[test]
+fn position_jump_combines_with_pattern_break() {
+    let mut module = make_module(&[(4, &[]), (4, &[])], &[0, 1, 0]);
+    module.num_channels = 2;
+    module.patterns[0].channels.push(vec![Row::default(); 4]);
+    module.patterns[1].channels.push(vec![Row::default(); 4]);
+
+    // Break on first channel, jump on second
+    module.patterns[0].channels[0][1] = effect(0x0D, 0x03);
+    module.patterns[0].channels[1][1] = effect(0x0B, 0x02);
+
+    assert_eq!(play_rows(&module), [(0, 0), (0, 1), (2, 3)]);
+}
+
+#[test]
+fn position_jump_back_counts_as_loop() {
+    let module = make_module(&[(2, &[]), (2, &[(1, effect(0x0B, 0x01))])], &[0, 1]);
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH * 2 * 4];
+
+    // All four rows played, then jumped back to second pattern
+    player.render(&mut buffer);
+    assert_eq!(player.loop_count, 1);
+    assert_eq!((player.pattern_order_index, player.row_index), (1, 0));
+
+    // Second pattern alone loops from now on
+    player.render(&mut buffer[..TICK_LENGTH * 2 * 2]);
+    assert_eq!(player.loop_count, 2);
+}
+
+#[test]
+fn pattern_loop_repeats_rows() {
+    let module = make_module(
+        &[(4, &[(1, effect(0x0E, 0x60)), (2, effect(0x0E, 0x62))])],
+        &[0],
+    );
+
+    assert_eq!(
+        play_rows(&module),
+        [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (0, 1),
+            (0, 2),
+            (0, 1),
+            (0, 2),
+            (0, 3)
+        ]
+    );
+}
+
+#[test]
+fn pattern_loop_from_pattern_start() {
+    // Without E60 loop starts from the first row
+    let module = make_module(&[(2, &[(1, effect(0x0E, 0x61))])], &[0]);
+
+    assert_eq!(play_rows(&module), [(0, 0), (0, 1), (0, 0), (0, 1)]);
+}
+
+#[test]
+fn pattern_delay_repeats_row() {
+    let module = make_module(&[(2, &[(0, effect(0x0E, 0xE2))])], &[0]);
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH];
+    let mut row_ticks = Vec::new();
+
+    while player.loop_count == 0 {
+        player.render(&mut buffer);
+        row_ticks.push(player.row_tick);
+    }
+
+    // First row takes 3 times the speed of 2 ticks
+    assert_eq!(row_ticks, [1, 2, 3, 4, 5, 0, 1, 0]);
+}
+
+#[test]
+fn speed_and_bpm_change() {
+    // Speed 1 makes every tick a row. At 150 BPM tick is 16.67 samples long.
+    let module = make_module(
+        &[(256, &[(0, effect(0x0F, 0x01)), (1, effect(0x0F, 150))])],
+        &[0],
+    );
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; (20 + 2500) * 2];
+
+    player.render(&mut buffer);
+    assert_eq!((player.speed, player.bpm), (1, 150));
+
+    // First row at 125 BPM and 150 rows at 150 BPM, without drifting
+    assert_eq!(player.row_index, 151);
+}
+
+#[test]
+fn speed_past_31_is_not_bpm() {
+    // S3M & IT Axx above 0x1F are loaded as effect 0x1C
+    let module = make_module(&[(2, &[(0, effect(0x1C, 0x40))])], &[0]);
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH];
+
+    player.render(&mut buffer);
+    assert_eq!((player.speed, player.bpm), (0x40, 125));
+}
+
+#[test]
+fn slowest_bpm_fits_tick_buffers() {
+    let mut module = make_module(&[(4, &[])], &[0]);
+    module.bpm = 32;
+
+    let mut player = Player::new(&module, 44100);
+    let mut buffer = vec![0i16; 44100 * 2];
+
+    // 3445.3 samples per tick
+    player.render(&mut buffer);
+    assert!((3445..=3446).contains(&player.samples_per_tick));
+}
+
+#[test]
+fn zero_sample_rate_renders_nothing() {
+    let module = make_module(&[(4, &[])], &[0]);
+    let mut player = Player::new(&module, 0);
+    let mut buffer = vec![0i16; TICK_LENGTH];
+
+    assert_eq!(player.render(&mut buffer), 0);
+    assert_eq!(player.row_index, 0);
+}
+
+#[test]
+fn global_volume_effects() {
+    let module = make_module(
+        &[(
+            4,
+            &[
+                (0, effect(0x10, 0x30)),
+                (1, effect(0x11, 0x04)),
+                (2, effect(0x11, 0x00)),
+                (3, effect(0x10, 0x50)),
+            ],
+        )],
+        &[0],
+    );
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH * 2];
+    let mut volumes = Vec::new();
+
+    // Two ticks per row, slides happen on the second one
+    for _ in 0..4 {
+        player.render(&mut buffer);
+        volumes.push(player.get_global_volume());
+    }
+
+    assert_eq!(volumes, [0x30, 0x2C, 0x28, 64]);
+}
+
+#[test]
+fn set_global_volume() {
+    let module = make_module(
+        &[(3, &[(1, effect(0x11, 0x04)), (2, effect(0x10, 0x10))])],
+        &[0],
+    );
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    player.set_global_volume(100);
+    assert_eq!(player.get_global_volume(), 64);
+
+    // Song's global volume effects continue from the set volume
+    player.set_global_volume(0x30);
+    let mut buffer = vec![0i16; TICK_LENGTH * 2];
+    let mut volumes = Vec::new();
+    for _ in 0..3 {
+        player.render(&mut buffer);
+        volumes.push(player.get_global_volume());
+    }
+
+    assert_eq!(volumes, [0x30, 0x2C, 0x10]);
+}
+
+#[test]
+fn set_volume() {
+    let module = make_module(&[(4, &[])], &[0]);
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    assert_eq!(player.get_volume(), 64);
+
+    player.set_volume(32);
+    assert_eq!(player.get_volume(), 32);
+
+    player.set_volume(100);
+    assert_eq!(player.get_volume(), 64);
+}
+
+#[test]
+fn volume_is_kept_by_global_volume_effects_and_reset() {
+    let mut module = make_module(&[(4, &[(0, note(49, 1)), (2, effect(0x10, 0x20))])], &[0]);
+    add_instrument(&mut module);
+
+    let render = |volume: usize| {
+        let mut player = Player::new(&module, SAMPLE_RATE);
+        let mut buffer = vec![0i16; TICK_LENGTH * 2 * 4];
+        player.set_volume(volume);
+        player.render(&mut buffer);
+
+        assert_eq!(player.get_global_volume(), 0x20);
+        assert_eq!(player.get_volume(), volume);
+
+        player.reset();
+        assert_eq!(player.get_global_volume(), 64);
+        assert_eq!(player.get_volume(), volume);
+
+        buffer
+    };
+
+    // Fade halves the output before and after set global volume
+    let full = render(64);
+    let faded = render(32);
+    for pos in [0, TICK_LENGTH * 2 * 3] {
+        assert!(full[pos] > 0);
+        assert_eq!(faded[pos], full[pos] / 2);
+    }
+}
+
+#[test]
+fn global_volume_effects_apply_to_all_channels() {
+    // Note on first channel, set global volume on the next row of given channel
+    let render = |effect_channel: usize| {
+        let mut module = make_module(&[(2, &[(0, note(49, 1))])], &[0]);
+        module.num_channels = 2;
+        module.patterns[0].channels.push(vec![Row::default(); 2]);
+        module.patterns[0].channels[effect_channel][1] = effect(0x10, 0x20);
+        add_instrument(&mut module);
+
+        let mut player = Player::new(&module, SAMPLE_RATE);
+        let mut buffer = vec![0i16; TICK_LENGTH * 3];
+        player.render(&mut buffer);
+        buffer
+    };
+
+    // Channels before the one with the effect get new volume on the same tick
+    let buffer = render(1);
+    assert!(buffer == render(0));
+    assert!(buffer[TICK_LENGTH * 2] < buffer[0]);
+}
+
+#[test]
+fn row_instrument_and_note_are_1_based() {
+    let mut module = make_module(&[(2, &[(0, note(49, 2)), (1, note(1, 1))])], &[0]);
+    add_instrument_with_value(&mut module, 1024);
+    add_instrument_with_value(&mut module, -1024);
+
+    let mut player = Player::new(&module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; TICK_LENGTH * 2 * 2];
+    player.render(&mut buffer);
+
+    // Instrument 2 on the first row, instrument 1 with the lowest note on the second.
+    // Without volume envelope instrument plays at full volume, panned to center.
+    assert_eq!(buffer[..2], [-508, -512]);
+    assert_eq!(buffer[TICK_LENGTH * 2..TICK_LENGTH * 2 + 2], [508, 512]);
+}
+
+#[test]
+fn channels_mix_with_saturation() {
+    // Three channels playing the same note, each at half of the sample value per side
+    let render = |value: i16| {
+        let mut module = make_module(&[(1, &[(0, note(49, 1))])], &[0]);
+        module.num_channels = 3;
+        for _ in 1..3 {
+            let rows = module.patterns[0].channels[0].clone();
+            module.patterns[0].channels.push(rows);
+        }
+        add_instrument_with_value(&mut module, value);
+
+        let mut player = Player::new(&module, SAMPLE_RATE);
+        let mut buffer = vec![0i16; TICK_LENGTH];
+        player.render(&mut buffer);
+        buffer
+    };
+
+    // Whole tick is clipped, including samples past the last 16 sample block
+    assert!(render(i16::MAX).iter().all(|&value| value == i16::MAX));
+    assert!(render(i16::MIN).iter().all(|&value| value == i16::MIN));
+}
@@ -0,0 +1,74 @@
+use xm_player::{Module, Pattern, Player, Row};
+
+const SAMPLE_RATE: usize = 48000;
+
+fn render(module: &Module, seconds: usize) -> Vec<i16> {
+    let mut player = Player::new(module, SAMPLE_RATE);
+    let mut buffer = vec![0i16; SAMPLE_RATE * 2];
+    let mut result = Vec::with_capacity(buffer.len() * seconds);
+
+    for _ in 0..seconds {
+        player.render(&mut buffer);
+        result.extend_from_slice(&buffer);
+    }
+
+    result
+}
+
+fn check_round_trip(file_name: &str) {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file_name);
+    let original = Module::load(path).unwrap();
+
+    let mut saved = Vec::new();
+    original.save_xm(&mut saved).unwrap();
+    let reloaded = Module::from_bytes(&saved).unwrap();
+
+    assert_eq!(reloaded.name, original.name);
+    assert_eq!(reloaded.pattern_order, original.pattern_order);
+    assert_eq!(reloaded.patterns.len(), original.patterns.len());
+    assert_eq!(reloaded.instruments.len(), original.instruments.len());
+
+    // Saving again gives exactly the same file
+    let mut saved_again = Vec::new();
+    reloaded.save_xm(&mut saved_again).unwrap();
+    assert!(saved == saved_again);
+
+    assert!(render(&reloaded, 30) == render(&original, 30));
+}
+
+#[test]
+fn song_round_trip() {
+    check_round_trip("song.xm");
+}
+
+#[test]
+fn unreal_round_trip() {
+    check_round_trip("unreal.xm");
+}
+
+#[test]
+fn note_cut_saved_as_silent_key_off() {
+    // S3M & IT note cut, with volume column that would keep key-off audible
+    let note_cut = Row {
+        note: 98,
+        volume: 0x10 + 48,
+        ..Row::default()
+    };
+    let module = Module {
+        patterns: vec![Pattern {
+            num_rows: 2,
+            channels: vec![vec![note_cut, Row::default()]],
+        }],
+        pattern_order: vec![0],
+        num_channels: 1,
+        tempo: 6,
+        bpm: 125,
+        ..Module::default()
+    };
+
+    let mut saved = Vec::new();
+    module.save_xm(&mut saved).unwrap();
+
+    let row = Module::from_bytes(&saved).unwrap().patterns[0].get_channel_row(0, 0);
+    assert_eq!((row.note, row.volume), (97, 0x10));
+}